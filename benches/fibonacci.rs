@@ -0,0 +1,25 @@
+// Rough timing of symbol heavy code, run with
+// cargo bench --bench fibonacci
+
+use lisp::{Exp, Interpreter};
+
+fn main() {
+    let interpreter = Interpreter::new();
+    interpreter
+        .eval_str(
+            "(defun bench-fibonacci (N)
+               (if (or (= N 0) (= N 1))
+                   1
+                   (+ (bench-fibonacci (- N 1)) (bench-fibonacci (- N 2)))))",
+        )
+        .unwrap();
+    let program = lisp::parse("(bench-fibonacci 22)").unwrap();
+
+    let runs = 5;
+    let start = std::time::Instant::now();
+    for _ in 0..runs {
+        let res = interpreter.eval(&program[0]).unwrap();
+        assert_eq!(res, Exp::Num(28657));
+    }
+    println!("fibonacci 22: {:?} per run", start.elapsed() / runs);
+}
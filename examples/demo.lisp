@@ -0,0 +1,55 @@
+(progn
+
+   (print (* (* 1 1 1 (+ 1 1) 1 1) (* (* 1 1) (* 1 1))))
+
+   (def identity (lambda (t) t))
+
+   (defun fibonacci (N)
+       (if (or (= N 0) (= N 1))
+           1
+           (+ (fibonacci (- N 1)) (fibonacci (- N 2)))))
+    (defun fibonacci-with-let (N)
+        (if (or (= N 0) (= N 1))
+            1
+            (let ((f1 (fibonacci (- N 1)))
+                  (f2 (fibonacci (- N 2))))
+                 (+ f1 f2))))
+
+   (print fibonacci)
+   (print (let () 2))
+
+   (print (let ((a 10)
+                (b 100))
+              (+ a b)))
+
+   (defun foreach (list fn)
+        (if list
+           (progn
+             (fn (car list))
+             (foreach (cdr list) fn))
+           (print 1234)))
+
+   (foreach (list 1 2 3 4 5 6) (lambda (x) (print x)))
+
+   (print (cdr (list 1 2 3 4)))
+   (print (car (list 1 2 3 4)))
+
+   (defun x (l) (print (identity l)) (x (+ l 1)))
+
+   (thread/spawn (lambda ()
+                     (print "hello from the thread")
+                     (dotimes 10 (lambda (i) (print "hiii")))))
+
+   (thread/spawn (lambda ()
+                     (print "hello from the other thread")
+                     (dotimes 10 (lambda (i) (print "hooo")))))
+
+   (dotimes 10 (lambda (x)
+       (thread/spawn (lambda ()
+                        (print "MORE THREADS")
+                        (dotimes 10 (lambda (i) (print x)))))))
+
+   (assert (= (fibonacci 5) (fibonacci-with-let 5)))
+
+   (fibonacci 5)
+   )
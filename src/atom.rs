@@ -0,0 +1,146 @@
+use crate::env::Env;
+use crate::eval::apply;
+use crate::exp::*;
+
+use std::sync::Arc;
+
+use arc_swap::{ArcSwap, ArcSwapOption};
+
+// The value of an atom is swapped in with compare-and-swap, readers never
+// wait and writers retry when another one got there first. Validators and
+// watchers are swapped the same way.
+
+/// A reference to a value that changes atomically.
+pub struct Atom {
+    value: ArcSwap<Exp>,
+    validator: ArcSwapOption<Exp>,
+    // (key, function) pairs, called as (function key atom old new).
+    watches: ArcSwap<Vec<(Exp, Exp)>>,
+}
+
+impl Atom {
+    pub fn new(value: Exp) -> Arc<Atom> {
+        Arc::new(Atom {
+            value: ArcSwap::from_pointee(value),
+            validator: ArcSwapOption::empty(),
+            watches: ArcSwap::from_pointee(vec![]),
+        })
+    }
+
+    /// The atom as a Lisp value.
+    pub fn to_exp(self: &Arc<Self>) -> Exp {
+        Object(Object::from_arc_with_type_name("atom", self.clone()))
+    }
+
+    pub fn deref(&self) -> Exp {
+        (**self.value.load()).clone()
+    }
+
+    /// Replaces the value with f of the current one, calling f again if
+    /// another thread changed it meanwhile. Returns the new value.
+    pub fn swap(
+        self: &Arc<Self>,
+        f: impl Fn(Exp) -> Result<Exp, LispErr>,
+        env: &Arc<Env>,
+    ) -> Result<Exp, LispErr> {
+        loop {
+            let old = self.value.load_full();
+            let new = Arc::new(f((*old).clone())?);
+            self.validate(&new, env)?;
+            let prev = self.value.compare_and_swap(&old, new.clone());
+            if Arc::ptr_eq(&prev, &old) {
+                self.notify(&old, &new, env)?;
+                return Ok((*new).clone());
+            }
+        }
+    }
+
+    /// Sets the value without looking at the current one.
+    pub fn reset(self: &Arc<Self>, value: Exp, env: &Arc<Env>) -> Result<Exp, LispErr> {
+        let new = Arc::new(value);
+        self.validate(&new, env)?;
+        let old = self.value.swap(new.clone());
+        self.notify(&old, &new, env)?;
+        Ok((*new).clone())
+    }
+
+    /// Sets the value to new if it's currently equal to old.
+    pub fn compare_and_set(
+        self: &Arc<Self>,
+        old: &Exp,
+        new: Exp,
+        env: &Arc<Env>,
+    ) -> Result<bool, LispErr> {
+        let new = Arc::new(new);
+        self.validate(&new, env)?;
+        loop {
+            let current = self.value.load_full();
+            if *current != *old {
+                return Ok(false);
+            }
+            let prev = self.value.compare_and_swap(&current, new.clone());
+            if Arc::ptr_eq(&prev, &current) {
+                self.notify(&current, &new, env)?;
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Makes every new value go through validator, a function that returns
+    /// false or raises to reject it. The current value has to pass too.
+    pub fn set_validator(&self, validator: Option<Exp>, env: &Arc<Env>) -> Result<(), LispErr> {
+        if let Some(validator) = &validator {
+            check(validator, &self.value.load(), env)?;
+        }
+        self.validator.store(validator.map(Arc::new));
+        Ok(())
+    }
+
+    /// Calls function with key, the atom, the old and the new value after
+    /// every change. Another watch with the same key is replaced.
+    pub fn add_watch(&self, key: Exp, function: Exp) {
+        self.watches.rcu(|watches| {
+            let mut watches: Vec<(Exp, Exp)> = watches
+                .iter()
+                .filter(|(other, _)| *other != key)
+                .cloned()
+                .collect();
+            watches.push((key.clone(), function.clone()));
+            watches
+        });
+    }
+
+    pub fn remove_watch(&self, key: &Exp) {
+        self.watches.rcu(|watches| {
+            watches
+                .iter()
+                .filter(|(other, _)| other != key)
+                .cloned()
+                .collect::<Vec<_>>()
+        });
+    }
+
+    fn validate(&self, value: &Exp, env: &Arc<Env>) -> Result<(), LispErr> {
+        match &*self.validator.load() {
+            Some(validator) => check(validator, value, env),
+            None => Ok(()),
+        }
+    }
+
+    fn notify(self: &Arc<Self>, old: &Exp, new: &Exp, env: &Arc<Env>) -> Result<(), LispErr> {
+        let watches = self.watches.load_full();
+        for (key, function) in watches.iter() {
+            let args = vec![key.clone(), self.to_exp(), old.clone(), new.clone()];
+            apply(function, args, env)?;
+        }
+        Ok(())
+    }
+}
+
+fn check(validator: &Exp, value: &Exp, env: &Arc<Env>) -> Result<(), LispErr> {
+    if to_bool(&apply(validator, vec![value.clone()], env)?) {
+        Ok(())
+    } else {
+        Err(format!("Validator rejected the value {value}").into())
+    }
+}
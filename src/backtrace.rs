@@ -0,0 +1,67 @@
+use crate::condition::Aside;
+use crate::error::Frame;
+use crate::exp::*;
+
+use std::cell::RefCell;
+
+// The calls to Lisp functions being evaluated on this thread, outermost first.
+// Entering a call only shares its form, the text is made when someone asks
+// for a backtrace.
+
+thread_local! {
+    static CALLS: RefCell<Vec<Form>> = const { RefCell::new(vec![]) };
+}
+
+/// Leaves the call when dropped, also when unwinding from a panic.
+pub(crate) struct CallGuard;
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        CALLS.with(|calls| calls.borrow_mut().pop());
+    }
+}
+
+pub(crate) fn enter(form: &Form) -> CallGuard {
+    CALLS.with(|calls| calls.borrow_mut().push(form.clone()));
+    CallGuard
+}
+
+/// Runs f with a call stack of its own.
+pub(crate) fn isolated<T>(f: impl FnOnce() -> T) -> T {
+    let _calls = Aside::new(&CALLS);
+    f()
+}
+
+const MAX_ARG_LEN: usize = 40;
+
+/// Short text for a list of arguments, long ones are cut.
+pub(crate) fn summarize(args: &[Exp], show: impl Fn(&Exp) -> String) -> String {
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| {
+            let text = show(arg);
+            match text.char_indices().nth(MAX_ARG_LEN) {
+                Some((end, _)) => format!("{}...", &text[..end]),
+                None => text,
+            }
+        })
+        .collect();
+    args.join(" ")
+}
+
+/// The function calls in progress, innermost first. Arguments are shown as they were
+/// written since their values aren't kept.
+pub fn current() -> Vec<Frame> {
+    CALLS.with(|calls| {
+        calls
+            .borrow()
+            .iter()
+            .rev()
+            .map(|form| Frame {
+                name: form[0].to_string(),
+                args: summarize(&form[1..], Exp::to_source),
+                span: form.span(),
+            })
+            .collect()
+    })
+}
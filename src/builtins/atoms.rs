@@ -0,0 +1,156 @@
+use crate::atom::Atom;
+use crate::error::LispError;
+use crate::eval::apply;
+use crate::exp::*;
+use crate::future::Promise;
+use crate::stm::Ref;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn atom_arg(arg: &Exp, name: &str) -> Result<Arc<Atom>, LispErr> {
+    match arg {
+        Object(object) => object.downcast::<Atom>(),
+        _ => None,
+    }
+    .ok_or_else(|| LispError::type_error("atom", arg).with_context(name))
+}
+
+fn deref(reference: &Exp, timeout: Option<(&Exp, &Exp)>) -> Result<Exp, LispErr> {
+    let Object(object) = reference else {
+        return Err(LispError::type_error("reference", reference).with_context("deref"));
+    };
+    if let Some(atom) = object.downcast_ref::<Atom>() {
+        return Ok(atom.deref());
+    }
+    if let Some(reference) = object.downcast::<Ref>() {
+        return reference.deref();
+    }
+    let Some(promise) = object.downcast_ref::<Promise>() else {
+        return Err(LispError::type_error("reference", reference).with_context("deref"));
+    };
+    match timeout {
+        None => promise.wait(None).unwrap(),
+        Some((Num(millis), default)) if *millis >= 0 => {
+            let deadline = Instant::now() + Duration::from_millis(*millis as u64);
+            promise
+                .wait(Some(deadline))
+                .unwrap_or_else(|| Ok(default.clone()))
+        }
+        Some((other, _)) => {
+            Err(LispError::type_error("non-negative integer", other).with_context("deref"))
+        }
+    }
+}
+
+pub fn init(env: &mut HashMap<String, Exp>) {
+    // (atom value :validator f)
+    env.insert(
+        "atom".into(),
+        Func(|args, env| {
+            let (value, validator) = match args {
+                [value] => (value, None),
+                [value, Keyword(option), validator] if option.name() == "validator" => {
+                    (value, Some(validator.clone()))
+                }
+                [_, option, _] => {
+                    return Err(LispError::type_error(":validator", option).with_context("atom"))
+                }
+                _ => return Err(LispError::arity("atom", "1 or 3", args.len())),
+            };
+            let atom = Atom::new(value.clone());
+            atom.set_validator(validator, env)?;
+            Ok(atom.to_exp())
+        }),
+    );
+
+    // Also read from @x. Futures and promises are waited for, optionally for
+    // a number of milliseconds after which the default is returned:
+    // (deref future millis default)
+    env.insert(
+        "deref".into(),
+        Func(|args, _| match args {
+            [reference] => deref(reference, None),
+            [reference, millis, default] => deref(reference, Some((millis, default))),
+            _ => Err(LispError::arity("deref", "1 or 3", args.len())),
+        }),
+    );
+
+    // (swap! atom f args...) sets the atom to (f value args...). f can run
+    // more than once, so it shouldn't have side effects.
+    env.insert(
+        "swap!".into(),
+        Func(|args, env| {
+            let [atom, function, extra @ ..] = args else {
+                return Err(LispError::arity("swap!", "at least 2", args.len()));
+            };
+            atom_arg(atom, "swap!")?.swap(
+                |value| {
+                    let mut call_args = vec![value];
+                    call_args.extend_from_slice(extra);
+                    apply(function, call_args, env)
+                },
+                env,
+            )
+        }),
+    );
+
+    env.insert(
+        "reset!".into(),
+        Func(|args, env| match args {
+            [atom, value] => atom_arg(atom, "reset!")?.reset(value.clone(), env),
+            _ => Err(LispError::arity("reset!", 2, args.len())),
+        }),
+    );
+
+    env.insert(
+        "compare-and-set!".into(),
+        Func(|args, env| match args {
+            [atom, old, new] => {
+                let atom = atom_arg(atom, "compare-and-set!")?;
+                Ok(Bool(atom.compare_and_set(old, new.clone(), env)?))
+            }
+            _ => Err(LispError::arity("compare-and-set!", 3, args.len())),
+        }),
+    );
+
+    // (set-validator! atom f), nil to remove it.
+    env.insert(
+        "set-validator!".into(),
+        Func(|args, env| match args {
+            [atom, validator] => {
+                let validator = match validator {
+                    List(None) => None,
+                    validator => Some(validator.clone()),
+                };
+                atom_arg(atom, "set-validator!")?.set_validator(validator, env)?;
+                Ok(List(None))
+            }
+            _ => Err(LispError::arity("set-validator!", 2, args.len())),
+        }),
+    );
+
+    // (add-watch atom key f) calls (f key atom old new) after every change.
+    env.insert(
+        "add-watch".into(),
+        Func(|args, _| match args {
+            [atom, key, function] => {
+                atom_arg(atom, "add-watch")?.add_watch(key.clone(), function.clone());
+                Ok(List(None))
+            }
+            _ => Err(LispError::arity("add-watch", 3, args.len())),
+        }),
+    );
+
+    env.insert(
+        "remove-watch".into(),
+        Func(|args, _| match args {
+            [atom, key] => {
+                atom_arg(atom, "remove-watch")?.remove_watch(key);
+                Ok(List(None))
+            }
+            _ => Err(LispError::arity("remove-watch", 2, args.len())),
+        }),
+    );
+}
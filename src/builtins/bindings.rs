@@ -0,0 +1,88 @@
+use crate::dynamic::with_bindings;
+use crate::env::{set_global, Env};
+use crate::error::LispError;
+use crate::eval::{eval, eval_many};
+use crate::exp::*;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// (binding ((var value)...) body...) evaluates every value, then runs body
+// with the dynamic variables bound to them.
+fn run_binding(name: &str, args: &[Exp], env: &Arc<Env>) -> Result<Exp, LispErr> {
+    let [Vector(pairs), body @ ..] = args else {
+        return match args.first() {
+            None => Err(LispError::arity(name, "at least 1", 0)),
+            Some(other) => Err(LispError::type_error("binding list", other).with_context(name)),
+        };
+    };
+    let mut bindings = vec![];
+    for pair in pairs {
+        let Vector(pair) = pair else {
+            return Err(format!("{name} bindings look like ((var value)...)").into());
+        };
+        let [Symbol(var), value] = pair.as_slice() else {
+            return Err(format!("{name} bindings look like ((var value)...)").into());
+        };
+        let cell = env.interpreter().global_cell(*var);
+        if !cell.is_dynamic() {
+            return Err(format!("{name}: {var} isn't a dynamic variable").into());
+        }
+        bindings.push((cell, eval(value, env)?));
+    }
+    with_bindings(bindings, || eval_many(body, env))
+}
+
+fn declare(name: &str, var: &Exp, env: &Arc<Env>) -> Result<Sym, LispErr> {
+    let Symbol(var) = var else {
+        return Err(LispError::type_error("symbol", var).with_context(name));
+    };
+    env.interpreter().global_cell(*var).make_dynamic();
+    Ok(*var)
+}
+
+pub fn init(env: &mut HashMap<String, Exp>) {
+    // (defvar name value) declares a dynamic variable, setting it only if it
+    // has no value yet.
+    env.insert(
+        "defvar".into(),
+        Macro(|args, env| {
+            match args {
+                [var] => {
+                    declare("defvar", var, env)?;
+                }
+                [var, value] => {
+                    let name = declare("defvar", var, env)?;
+                    if env.interpreter().global_cell(name).peek().is_none() {
+                        set_global(var, value, env)?;
+                    }
+                }
+                _ => return Err(LispError::arity("defvar", "1 or 2", args.len())),
+            }
+            Ok(vec![])
+        }),
+    );
+
+    // Like defvar, but always sets the value.
+    env.insert(
+        "defparameter".into(),
+        Macro(|args, env| match args {
+            [var, value] => {
+                declare("defparameter", var, env)?;
+                set_global(var, value, env)?;
+                Ok(vec![])
+            }
+            _ => Err(LispError::arity("defparameter", 2, args.len())),
+        }),
+    );
+
+    env.insert(
+        "binding".into(),
+        Special(|args, env| run_binding("binding", args, env)),
+    );
+
+    env.insert(
+        "parameterize".into(),
+        Special(|args, env| run_binding("parameterize", args, env)),
+    );
+}
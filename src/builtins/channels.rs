@@ -0,0 +1,157 @@
+use crate::channel::{select, Channel, Received};
+use crate::env::Env;
+use crate::error::LispError;
+use crate::eval::{eval, eval_many};
+use crate::exp::*;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn channel_arg<'a>(arg: &'a Exp, name: &str) -> Result<&'a Channel, LispErr> {
+    match arg {
+        Object(object) if object.is::<Channel>() => Ok(object.downcast_ref().unwrap()),
+        other => Err(LispError::type_error("channel", other).with_context(name)),
+    }
+}
+
+fn deadline(millis: &Exp, name: &str) -> Result<Instant, LispErr> {
+    match millis {
+        Num(millis) if *millis >= 0 => Ok(Instant::now() + Duration::from_millis(*millis as u64)),
+        other => Err(LispError::type_error("non-negative integer", other).with_context(name)),
+    }
+}
+
+// What a receive evaluates to: the value, or the default when the channel is
+// closed or nothing arrived.
+fn received(received: Received, default: Option<&Exp>) -> Exp {
+    match received {
+        Received::Value(value) => value,
+        Received::Empty | Received::Closed => default.cloned().unwrap_or(List(None)),
+    }
+}
+
+// (select
+//   (channel var body...)
+//   (:timeout millis body...))
+//
+// Waits until one of the channels has a value or is closed, then evaluates
+// the body of its clause with var bound to the value, nil if it was closed.
+// The first clause that is ready wins. Without a :timeout clause it waits
+// as long as it takes.
+fn run_select(args: &[Exp], env: &Arc<Env>) -> Result<Exp, LispErr> {
+    if args.is_empty() {
+        return Err(LispError::arity("select", "at least 1", 0));
+    }
+    let mut channels = vec![];
+    let mut clauses = vec![];
+    let mut timeout = None;
+    for clause in args {
+        let Vector(clause) = clause else {
+            return Err("select clauses look like (channel var body...)".into());
+        };
+        match clause.as_slice() {
+            [Keyword(keyword), millis, body @ ..] if keyword.name() == "timeout" => {
+                timeout = Some((deadline(&eval(millis, env)?, "select")?, body));
+            }
+            [channel, Symbol(var), body @ ..] => {
+                channels.push(channel_arg(&eval(channel, env)?, "select")?.clone());
+                clauses.push((*var, body));
+            }
+            _ => return Err("select clauses look like (channel var body...)".into()),
+        }
+    }
+
+    match select(&channels, timeout.as_ref().map(|(deadline, _)| *deadline))? {
+        Some((index, got)) => {
+            let (var, body) = clauses[index];
+            let mut clause_env = Env::from_upper(env);
+            clause_env.insert(var, received(got, None));
+            eval_many(body, &Arc::new(clause_env))
+        }
+        None => match timeout {
+            Some((_, body)) => eval_many(body, env),
+            None => Ok(List(None)),
+        },
+    }
+}
+
+pub fn init(env: &mut HashMap<String, Exp>) {
+    env.insert(
+        "chan".into(),
+        Func(|args, _| {
+            let capacity = match args {
+                [] => None,
+                [Num(capacity)] if *capacity > 0 => Some(*capacity as usize),
+                [other] => {
+                    return Err(
+                        LispError::type_error("positive integer", other).with_context("chan")
+                    )
+                }
+                _ => return Err(LispError::arity("chan", "0 or 1", args.len())),
+            };
+            Ok(Object(Object::with_type_name(
+                "channel",
+                Channel::new(capacity),
+            )))
+        }),
+    );
+
+    env.insert(
+        "send".into(),
+        Func(|args, _| match args {
+            [channel, value] => {
+                channel_arg(channel, "send")?.send(value.clone())?;
+                Ok(List(None))
+            }
+            _ => Err(LispError::arity("send", 2, args.len())),
+        }),
+    );
+
+    env.insert(
+        "recv".into(),
+        Func(|args, _| match args {
+            [channel, default @ ..] if default.len() <= 1 => {
+                let got = channel_arg(channel, "recv")?.recv(None)?;
+                Ok(received(got, default.first()))
+            }
+            _ => Err(LispError::arity("recv", "1 or 2", args.len())),
+        }),
+    );
+
+    env.insert(
+        "try-recv".into(),
+        Func(|args, _| match args {
+            [channel, default @ ..] if default.len() <= 1 => {
+                let got = channel_arg(channel, "try-recv")?.try_recv();
+                Ok(received(got, default.first()))
+            }
+            _ => Err(LispError::arity("try-recv", "1 or 2", args.len())),
+        }),
+    );
+
+    env.insert(
+        "recv-timeout".into(),
+        Func(|args, _| match args {
+            [channel, millis, default @ ..] if default.len() <= 1 => {
+                let channel = channel_arg(channel, "recv-timeout")?;
+                let got = channel.recv(Some(deadline(millis, "recv-timeout")?))?;
+                Ok(received(got, default.first()))
+            }
+            _ => Err(LispError::arity("recv-timeout", "2 or 3", args.len())),
+        }),
+    );
+
+    env.insert(
+        "close".into(),
+        Func(|args, _| match args {
+            [channel] => {
+                channel_arg(channel, "close")?.close();
+                Ok(List(None))
+            }
+            _ => Err(LispError::arity("close", 1, args.len())),
+        }),
+    );
+
+    env.insert("select".into(), Special(run_select));
+}
@@ -0,0 +1,105 @@
+use crate::builtins::wrong_args;
+use crate::error::LispError;
+use crate::exp::list::{dolist, list_from_slice};
+use crate::exp::*;
+use crate::limits;
+
+use std::collections::HashMap;
+
+fn one_char<'a>(args: &'a [Exp], name: &str) -> Result<&'a char, LispErr> {
+    match args {
+        [Char(c)] => Ok(c),
+        [_] => Err(format!("{name} argument is not a character").into()),
+        _ => Err(LispError::arity(name, 1, args.len())),
+    }
+}
+
+pub fn init(env: &mut HashMap<String, Exp>) {
+    env.insert(
+        "char->integer".into(),
+        Func(|args, _| Ok(Num(*one_char(args, "char->integer")? as i64))),
+    );
+
+    env.insert(
+        "integer->char".into(),
+        Func(|args, _| {
+            let [Num(n)] = args else {
+                return Err(wrong_args("integer->char", args, 1));
+            };
+            u32::try_from(*n)
+                .ok()
+                .and_then(char::from_u32)
+                .map(Char)
+                .ok_or_else(|| format!("{n} is not a valid character code").into())
+        }),
+    );
+
+    env.insert(
+        "string-ref".into(),
+        Func(|args, _| {
+            let [Str(string), Num(index)] = args else {
+                return Err(wrong_args("string-ref", args, 2));
+            };
+            usize::try_from(*index)
+                .ok()
+                .and_then(|index| string.chars().nth(index))
+                .map(Char)
+                .ok_or_else(|| format!("string-ref index {index} out of range").into())
+        }),
+    );
+
+    env.insert(
+        "string->list".into(),
+        Func(|args, _| {
+            let [Str(string)] = args else {
+                return Err(wrong_args("string->list", args, 1));
+            };
+            let chars: Vec<Exp> = string.chars().map(Char).collect();
+            Ok(List(list_from_slice(&chars)))
+        }),
+    );
+
+    env.insert(
+        "list->string".into(),
+        Func(|args, _| {
+            let [List(list)] = args else {
+                return Err(wrong_args("list->string", args, 1));
+            };
+            let mut string = String::new();
+            dolist(list, |exp| match exp {
+                Char(c) => {
+                    string.push(*c);
+                    Ok(())
+                }
+                other => Err(format!("list->string element {other} is not a character").into()),
+            })?;
+            limits::allocate(string.len());
+            Ok(Str(string))
+        }),
+    );
+
+    env.insert(
+        "char-alphabetic?".into(),
+        Func(|args, _| Ok(Bool(one_char(args, "char-alphabetic?")?.is_alphabetic()))),
+    );
+
+    env.insert(
+        "char-numeric?".into(),
+        Func(|args, _| Ok(Bool(one_char(args, "char-numeric?")?.is_numeric()))),
+    );
+
+    env.insert(
+        "char-whitespace?".into(),
+        Func(|args, _| Ok(Bool(one_char(args, "char-whitespace?")?.is_whitespace()))),
+    );
+
+    env.insert(
+        "char-upper-case?".into(),
+        Func(|args, _| Ok(Bool(one_char(args, "char-upper-case?")?.is_uppercase()))),
+    );
+
+    env.insert(
+        "char-lower-case?".into(),
+        Func(|args, _| Ok(Bool(one_char(args, "char-lower-case?")?.is_lowercase()))),
+    );
+}
@@ -0,0 +1,139 @@
+use crate::condition::{
+    active_restarts, condition_kind, invoke_restart, signal, signal_error, with_handlers,
+    with_restarts, Handler, Signaled,
+};
+use crate::env::Env;
+use crate::error::LispError;
+use crate::eval::{eval, eval_many};
+use crate::exp::list::list_from_slice;
+use crate::exp::*;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// (handler-bind ((:kind handler) ...) body...)
+//
+// Handlers are functions of the condition. They run where the condition is
+// signaled, before anything unwinds, and either return to decline it or
+// leave through invoke-restart or an error.
+fn run_handler_bind(args: &[Exp], env: &Arc<Env>) -> Result<Exp, LispErr> {
+    let Some((bindings, body)) = args.split_first() else {
+        return Err(LispError::arity("handler-bind", "at least 1", 0));
+    };
+    let Vector(bindings) = bindings else {
+        return Err("handler-bind needs a list of (:kind handler) bindings".into());
+    };
+    let mut handlers = vec![];
+    for binding in bindings {
+        let Vector(binding) = binding else {
+            return Err("handler-bind bindings look like (:kind handler)".into());
+        };
+        let [Keyword(kind), handler] = binding.as_slice() else {
+            return Err("handler-bind bindings look like (:kind handler)".into());
+        };
+        handlers.push(Handler::Bind(kind.clone(), eval(handler, env)?));
+    }
+    // The first binding is the innermost, so it's tried first.
+    handlers.reverse();
+    with_handlers(handlers, || {
+        eval_many(body, env).map_err(|err| signal_error(err, env))
+    })
+}
+
+// (restart-case form
+//   (:name (params...) body...)
+//   ...)
+//
+// Evaluates form with the restarts available. Invoking one unwinds back here
+// and evaluates its body with the arguments bound to params.
+fn run_restart_case(args: &[Exp], env: &Arc<Env>) -> Result<Exp, LispErr> {
+    let Some((form, clauses)) = args.split_first() else {
+        return Err(LispError::arity("restart-case", "at least 1", 0));
+    };
+    let mut restarts = vec![];
+    let mut bodies = vec![];
+    for clause in clauses {
+        let Vector(clause) = clause else {
+            return Err("restart-case clauses look like (:name (params...) body...)".into());
+        };
+        let [Keyword(name), Vector(params), body @ ..] = clause.as_slice() else {
+            return Err("restart-case clauses look like (:name (params...) body...)".into());
+        };
+        let params = params
+            .iter()
+            .map(|param| match param {
+                Symbol(sym) => Ok(*sym),
+                other => Err(LispError::type_error("symbol", other)),
+            })
+            .collect::<Result<Vec<Sym>, LispErr>>()?;
+        restarts.push((name.clone(), params));
+        bodies.push(body);
+    }
+
+    match with_restarts(restarts.clone(), env, || eval(form, env))? {
+        Ok(value) => Ok(value),
+        Err((index, args)) => {
+            let (name, params) = &restarts[index];
+            if args.len() != params.len() {
+                return Err(LispError::arity(name.to_string(), params.len(), args.len()));
+            }
+            let mut restart_env = Env::from_upper(env);
+            for (param, arg) in params.iter().zip(args) {
+                restart_env.insert(*param, arg);
+            }
+            eval_many(bodies[index], &Arc::new(restart_env))
+        }
+    }
+}
+
+pub fn init(env: &mut HashMap<String, Exp>) {
+    env.insert(
+        "signal".into(),
+        Func(|args, env| {
+            let [condition] = args else {
+                return Err(LispError::arity("signal", 1, args.len()));
+            };
+            match signal(condition, env)? {
+                Signaled::Declined => Ok(List(None)),
+                Signaled::Caught => Err(LispError::user(condition.clone()).mark_signaled()),
+            }
+        }),
+    );
+
+    env.insert("handler-bind".into(), Special(run_handler_bind));
+
+    env.insert("restart-case".into(), Special(run_restart_case));
+
+    env.insert(
+        "invoke-restart".into(),
+        Func(|args, _| match args {
+            [Keyword(name), rest @ ..] => invoke_restart(name, rest.to_vec()),
+            [other, ..] => {
+                Err(LispError::type_error("keyword", other).with_context("invoke-restart"))
+            }
+            [] => Err(LispError::arity("invoke-restart", "at least 1", 0)),
+        }),
+    );
+
+    env.insert(
+        "compute-restarts".into(),
+        Func(|args, _| {
+            if !args.is_empty() {
+                return Err(LispError::arity("compute-restarts", 0, args.len()));
+            }
+            let names: Vec<Exp> = active_restarts()
+                .into_iter()
+                .map(|restart| Keyword(restart.name))
+                .collect();
+            Ok(List(list_from_slice(&names)))
+        }),
+    );
+
+    env.insert(
+        "condition-kind".into(),
+        Func(|args, _| match args {
+            [condition] => Ok(Keyword(condition_kind(condition))),
+            _ => Err(LispError::arity("condition-kind", 1, args.len())),
+        }),
+    );
+}
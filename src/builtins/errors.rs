@@ -0,0 +1,155 @@
+use crate::backtrace;
+use crate::condition::{catch_matches, with_handlers, Handler};
+use crate::env::Env;
+use crate::error::{ErrorKind, LispError};
+use crate::eval::{eval, eval_many};
+use crate::exp::list::list_from_slice;
+use crate::exp::*;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// (try body...
+//   (catch :type-error e handler...)
+//   (catch e handler...)
+//   (finally cleanup...))
+//
+// A catch without a kind takes any error, and :error matches every kind,
+// except cancelling, timeouts and resource limits that only a catch naming
+// their kind takes. The variable gets the raised value, or a {:kind
+// :message} map for errors that come from the interpreter itself. finally
+// runs however the rest ended.
+
+struct Catch<'a> {
+    kind: Option<Keyword>,
+    var: Sym,
+    body: &'a [Exp],
+}
+
+fn clause_name(exp: &Exp) -> Option<Arc<str>> {
+    match exp {
+        Vector(form) => match form.first() {
+            Some(Symbol(head)) => Some(head.name()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn parse_catch(clause: &[Exp]) -> Result<Catch<'_>, LispErr> {
+    match clause {
+        [_, Keyword(kind), Symbol(var), body @ ..] => Ok(Catch {
+            kind: Some(kind.clone()),
+            var: *var,
+            body,
+        }),
+        [_, Symbol(var), body @ ..] => Ok(Catch {
+            kind: None,
+            var: *var,
+            body,
+        }),
+        _ => Err("catch clauses look like (catch :kind var body...)".into()),
+    }
+}
+
+fn run_try(args: &[Exp], env: &Arc<Env>) -> Result<Exp, LispErr> {
+    let mut body = vec![];
+    let mut catches = vec![];
+    let mut finally: Option<&[Exp]> = None;
+    for arg in args {
+        match (clause_name(arg).as_deref(), arg) {
+            (Some("catch"), Vector(clause)) => catches.push(parse_catch(clause)?),
+            (Some("finally"), Vector(clause)) => finally = Some(&clause[1..]),
+            _ if catches.is_empty() && finally.is_none() => body.push(arg.clone()),
+            _ => return Err("try body has to come before catch and finally".into()),
+        }
+    }
+
+    // Handlers outside don't get to see what this catches.
+    let kinds = catches.iter().map(|catch| catch.kind.clone()).collect();
+    let result = match with_handlers(vec![Handler::Catch(kinds)], || eval_many(&body, env)) {
+        // Restarts and retries pass through to their restart-case or dosync.
+        Err(err) if err.is_transfer() => Err(err),
+        Err(err) => {
+            let kind = err.kind_keyword();
+            match catches
+                .iter()
+                .find(|catch| catch_matches(catch.kind.as_ref(), &kind))
+            {
+                Some(catch) => {
+                    let mut handler_env = Env::from_upper(env);
+                    handler_env.insert(catch.var, err.value());
+                    eval_many(catch.body, &Arc::new(handler_env))
+                }
+                None => Err(err),
+            }
+        }
+        ok => ok,
+    };
+
+    // Errors in the cleanup replace the result.
+    if let Some(cleanup) = finally {
+        eval_many(cleanup, env)?;
+    }
+    result
+}
+
+pub fn init(env: &mut HashMap<String, Exp>) {
+    env.insert(
+        "error".into(),
+        Func(|args, _| match args {
+            [value] => Err(LispError::user(value.clone())),
+            _ => Err(LispError::arity("error", 1, args.len())),
+        }),
+    );
+
+    env.insert(
+        "raise".into(),
+        Func(|args, _| match args {
+            [value] => Err(LispError::user(value.clone())),
+            _ => Err(LispError::arity("raise", 1, args.len())),
+        }),
+    );
+
+    env.insert("try".into(), Special(run_try));
+
+    env.insert(
+        "backtrace".into(),
+        Func(|args, _| {
+            if !args.is_empty() {
+                return Err(LispError::arity("backtrace", 0, args.len()));
+            }
+            let key = |name| Keyword(Keyword::intern(name));
+            let frames: Vec<Exp> = backtrace::current()
+                .into_iter()
+                .map(|frame| {
+                    let mut map = Map::new()
+                        .insert(key("name"), Str(frame.name))
+                        .insert(key("args"), Str(frame.args));
+                    if let Some(span) = frame.span {
+                        map = map
+                            .insert(key("line"), Num(span.line.into()))
+                            .insert(key("column"), Num(span.column.into()));
+                    }
+                    Map(map)
+                })
+                .collect();
+            Ok(List(list_from_slice(&frames)))
+        }),
+    );
+
+    env.insert(
+        "assert".into(),
+        Macro(|args, env| {
+            if args.is_empty() {
+                return Err(LispError::arity("assert", "at least 1", 0));
+            }
+            for arg in args {
+                if !to_bool(&eval(arg, env)?) {
+                    return Err(LispError::new(ErrorKind::Assertion(arg.to_source())));
+                }
+            }
+            Ok(vec![List(None)])
+        }),
+    );
+}
@@ -0,0 +1,101 @@
+use crate::env::Env;
+use crate::error::LispError;
+use crate::eval::apply;
+use crate::exp::list::{dolist, list_from_slice};
+use crate::exp::*;
+use crate::future::{future, Promise};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn promise_arg(arg: &Exp, name: &str) -> Result<Arc<Promise>, LispErr> {
+    match arg {
+        Object(object) => object.downcast::<Promise>(),
+        _ => None,
+    }
+    .ok_or_else(|| LispError::type_error("promise", arg).with_context(name))
+}
+
+// Calls function on every element of the list on the pool, and waits for
+// all the results, in the order of the list.
+fn parallel_map(args: &[Exp], env: &Arc<Env>, name: &str) -> Result<Vec<Exp>, LispErr> {
+    let [function, List(list)] = args else {
+        return Err(match args {
+            [_, other] => LispError::type_error("list", other).with_context(name),
+            _ => LispError::arity(name, 2, args.len()),
+        });
+    };
+    let mut promises = vec![];
+    dolist(list, |item| {
+        let (function, item, env) = (function.clone(), item.clone(), env.clone());
+        promises.push(future(move || apply(&function, vec![item], &env)));
+        Ok(())
+    })?;
+    // Everything is waited for before raising, nothing keeps running behind
+    // the caller's back.
+    let results: Vec<Result<Exp, LispErr>> = promises
+        .iter()
+        .map(|promise| promise.wait(None).unwrap())
+        .collect();
+    results.into_iter().collect()
+}
+
+pub fn init(env: &mut HashMap<String, Exp>) {
+    // (future thunk) calls thunk on the worker pool, deref waits for the
+    // result.
+    env.insert(
+        "future".into(),
+        Func(|args, env| match args {
+            [thunk @ (Lambda(_) | Func(_) | Native(_))] => {
+                let (thunk, env) = (thunk.clone(), env.clone());
+                Ok(future(move || apply(&thunk, vec![], &env)).to_exp("future"))
+            }
+            [other] => Err(LispError::type_error("function", other).with_context("future")),
+            _ => Err(LispError::arity("future", 1, args.len())),
+        }),
+    );
+
+    env.insert(
+        "promise".into(),
+        Func(|args, _| match args {
+            [] => Ok(Promise::new().to_exp("promise")),
+            _ => Err(LispError::arity("promise", 0, args.len())),
+        }),
+    );
+
+    // Returns whether the value was delivered, only the first one is.
+    env.insert(
+        "deliver".into(),
+        Func(|args, _| match args {
+            [promise, value] => {
+                let promise = promise_arg(promise, "deliver")?;
+                Ok(Bool(promise.deliver(Ok(value.clone()))))
+            }
+            _ => Err(LispError::arity("deliver", 2, args.len())),
+        }),
+    );
+
+    env.insert(
+        "realized?".into(),
+        Func(|args, _| match args {
+            [promise] => Ok(Bool(promise_arg(promise, "realized?")?.is_realized())),
+            _ => Err(LispError::arity("realized?", 1, args.len())),
+        }),
+    );
+
+    env.insert(
+        "pmap".into(),
+        Func(|args, env| {
+            let results = parallel_map(args, env, "pmap")?;
+            Ok(List(list_from_slice(&results)))
+        }),
+    );
+
+    env.insert(
+        "pfor-each".into(),
+        Func(|args, env| {
+            parallel_map(args, env, "pfor-each")?;
+            Ok(List(None))
+        }),
+    );
+}
@@ -0,0 +1,240 @@
+use crate::builtins::wrong_args;
+use crate::error::LispError;
+use crate::eval::apply;
+use crate::exp::list::{dolist, list_from_slice};
+use crate::exp::*;
+
+use std::collections::HashMap;
+
+fn nil() -> Exp {
+    List(None)
+}
+
+pub fn init(env: &mut HashMap<String, Exp>) {
+    env.insert(
+        "hash-map".into(),
+        Func(|args, _| {
+            if !args.len().is_multiple_of(2) {
+                return Err(LispError::arity(
+                    "hash-map",
+                    "an even number of",
+                    args.len(),
+                ));
+            }
+            let map = args
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect();
+            Ok(Map(map))
+        }),
+    );
+
+    env.insert(
+        "hash-set".into(),
+        Func(|args, _| Ok(Set(args.iter().cloned().collect()))),
+    );
+
+    env.insert(
+        "get".into(),
+        Func(|args, _| {
+            let (coll, key, default) = match args {
+                [coll, key] => (coll, key, nil()),
+                [coll, key, default] => (coll, key, default.clone()),
+                _ => return Err(LispError::arity("get", "2 or 3", args.len())),
+            };
+            match coll {
+                Map(map) => Ok(map.get(key).cloned().unwrap_or(default)),
+                Set(set) if set.contains(key) => Ok(key.clone()),
+                Set(_) => Ok(default),
+                List(None) => Ok(default),
+                _ => Err(format!("get argument {coll} is not a map or set").into()),
+            }
+        }),
+    );
+
+    env.insert(
+        "assoc".into(),
+        Func(|args, _| {
+            if args.len() < 3 || args.len().is_multiple_of(2) {
+                return Err(LispError::arity("assoc", "an odd number of", args.len()));
+            }
+            let Some((Map(map), pairs)) = args.split_first() else {
+                return Err("assoc first argument is not a map".into());
+            };
+            let map = pairs.chunks(2).fold(map.clone(), |map, pair| {
+                map.insert(pair[0].clone(), pair[1].clone())
+            });
+            Ok(Map(map))
+        }),
+    );
+
+    env.insert(
+        "dissoc".into(),
+        Func(|args, _| {
+            let Some((first, keys)) = args.split_first() else {
+                return Err(LispError::arity("dissoc", "at least 1", 0));
+            };
+            let Map(map) = first else {
+                return Err("dissoc first argument is not a map".into());
+            };
+            Ok(Map(keys
+                .iter()
+                .fold(map.clone(), |map, key| map.remove(key))))
+        }),
+    );
+
+    env.insert(
+        "conj".into(),
+        Func(|args, _| {
+            let Some((first, keys)) = args.split_first() else {
+                return Err(LispError::arity("conj", "at least 1", 0));
+            };
+            let Set(set) = first else {
+                return Err("conj first argument is not a set".into());
+            };
+            Ok(Set(keys
+                .iter()
+                .fold(set.clone(), |set, key| set.insert(key.clone()))))
+        }),
+    );
+
+    env.insert(
+        "disj".into(),
+        Func(|args, _| {
+            let Some((first, keys)) = args.split_first() else {
+                return Err(LispError::arity("disj", "at least 1", 0));
+            };
+            let Set(set) = first else {
+                return Err("disj first argument is not a set".into());
+            };
+            Ok(Set(keys
+                .iter()
+                .fold(set.clone(), |set, key| set.remove(key))))
+        }),
+    );
+
+    env.insert(
+        "contains?".into(),
+        Func(|args, _| match args {
+            [Map(map), key] => Ok(Bool(map.contains_key(key))),
+            [Set(set), key] => Ok(Bool(set.contains(key))),
+            [_, _] => Err("contains? first argument is not a map or set".into()),
+            _ => Err(LispError::arity("contains?", 2, args.len())),
+        }),
+    );
+
+    env.insert(
+        "keys".into(),
+        Func(|args, _| {
+            let [Map(map)] = args else {
+                return Err(wrong_args("keys", args, 1));
+            };
+            let keys: Vec<Exp> = map.keys().cloned().collect();
+            Ok(List(list_from_slice(&keys)))
+        }),
+    );
+
+    env.insert(
+        "vals".into(),
+        Func(|args, _| {
+            let [Map(map)] = args else {
+                return Err(wrong_args("vals", args, 1));
+            };
+            let vals: Vec<Exp> = map.values().cloned().collect();
+            Ok(List(list_from_slice(&vals)))
+        }),
+    );
+
+    env.insert(
+        "merge".into(),
+        Func(|args, _| {
+            let mut merged = Map::new();
+            for arg in args {
+                match arg {
+                    Map(map) if merged.is_empty() => merged = map.clone(),
+                    Map(map) => {
+                        for (k, v) in map.iter() {
+                            merged = merged.insert(k.clone(), v.clone());
+                        }
+                    }
+                    List(None) => {}
+                    other => return Err(format!("merge argument {other} is not a map").into()),
+                }
+            }
+            Ok(Map(merged))
+        }),
+    );
+
+    env.insert(
+        "update".into(),
+        Func(|args, env| {
+            if args.len() < 3 {
+                return Err(LispError::arity("update", "at least 3", args.len()));
+            }
+            let [Map(map), key, func, extra @ ..] = args else {
+                return Err("update first argument is not a map".into());
+            };
+            let old = map.get(key).cloned().unwrap_or_else(nil);
+            let mut call_args = vec![old];
+            call_args.extend_from_slice(extra);
+            let new = apply(func, call_args, env)?;
+            Ok(Map(map.insert(key.clone(), new)))
+        }),
+    );
+
+    env.insert(
+        "count".into(),
+        Func(|args, _| {
+            let [coll] = args else {
+                return Err(wrong_args("count", args, 1));
+            };
+            let count = match coll {
+                Map(map) => map.len(),
+                Set(set) => set.len(),
+                Str(string) => string.chars().count(),
+                Vector(v) => v.len(),
+                List(list) => {
+                    let mut count = 0;
+                    dolist(list, |_| {
+                        count += 1;
+                        Ok(())
+                    })?;
+                    count
+                }
+                other => return Err(format!("count argument {other} is not a collection").into()),
+            };
+            Ok(Num(count as i64))
+        }),
+    );
+
+    env.insert(
+        "equal?".into(),
+        Func(|args, _| {
+            let Some((first, rest)) = args.split_first() else {
+                return Err(LispError::arity("equal?", "at least 1", 0));
+            };
+            Ok(Bool(rest.iter().all(|x| x == first)))
+        }),
+    );
+
+    env.insert(
+        "keyword".into(),
+        Func(|args, _| match args {
+            [Str(name)] if name.is_empty() => Err("keyword: the name is empty".into()),
+            [Str(name)] => Ok(Keyword(Keyword::intern(name))),
+            [Keyword(keyword)] => Ok(Keyword(keyword.clone())),
+            [other] => {
+                Err(LispError::type_error("string or keyword", other).with_context("keyword"))
+            }
+            _ => Err(LispError::arity("keyword", 1, args.len())),
+        }),
+    );
+
+    env.insert(
+        "keyword?".into(),
+        Func(|args, _| match args {
+            [arg] => Ok(Bool(matches!(arg, Keyword(_)))),
+            _ => Err(LispError::arity("keyword?", 1, args.len())),
+        }),
+    );
+}
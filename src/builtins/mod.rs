@@ -0,0 +1,25 @@
+use crate::error::LispError;
+use crate::exp::Exp;
+
+pub mod atoms;
+pub mod bindings;
+pub mod channels;
+pub mod chars;
+pub mod conditions;
+pub mod errors;
+pub mod futures;
+pub mod maps;
+pub mod objects;
+pub mod refs;
+pub mod sandboxes;
+pub mod threads;
+
+/// The error for arguments a builtin taking a fixed number of them can't use:
+/// an arity error if the count is off, a generic one otherwise.
+pub(crate) fn wrong_args(name: &str, args: &[Exp], arity: usize) -> LispError {
+    if args.len() != arity {
+        LispError::arity(name, arity, args.len())
+    } else {
+        format!("Wrong arguments to {name}").into()
+    }
+}
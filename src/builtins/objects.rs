@@ -0,0 +1,63 @@
+use crate::builtins::wrong_args;
+use crate::error::LispError;
+use crate::exp::list::list_from_slice;
+use crate::exp::*;
+
+use std::collections::HashMap;
+
+pub fn init(env: &mut HashMap<String, Exp>) {
+    env.insert(
+        "invoke".into(),
+        Func(|args, env| {
+            if args.len() < 2 {
+                return Err(LispError::arity("invoke", "at least 2", args.len()));
+            }
+            let [Object(object), method, rest @ ..] = args else {
+                return Err("invoke first argument is not an object".into());
+            };
+            let name = match method {
+                Keyword(keyword) => Sym::intern(keyword.name()),
+                Symbol(sym) => *sym,
+                other => return Err(format!("invoke method name {other} is not a keyword").into()),
+            };
+            let Some(method) = env.interpreter().find_method(object, name) else {
+                return Err(format!("{} has no method {name}", object.type_name()).into());
+            };
+            let mut method_args = vec![args[0].clone()];
+            method_args.extend_from_slice(rest);
+            method.call(&method_args, env)
+        }),
+    );
+
+    env.insert(
+        "object?".into(),
+        Func(|args, _| match args {
+            [arg] => Ok(Bool(matches!(arg, Object(_)))),
+            _ => Err(LispError::arity("object?", 1, args.len())),
+        }),
+    );
+
+    env.insert(
+        "type-of".into(),
+        Func(|args, _| match args {
+            [arg] => Ok(Str(arg.type_name().into())),
+            _ => Err(LispError::arity("type-of", 1, args.len())),
+        }),
+    );
+
+    env.insert(
+        "object-methods".into(),
+        Func(|args, env| {
+            let [Object(object)] = args else {
+                return Err(wrong_args("object-methods", args, 1));
+            };
+            let names: Vec<Exp> = env
+                .interpreter()
+                .method_names(object)
+                .into_iter()
+                .map(|name| Keyword(Keyword::intern(&name.name())))
+                .collect();
+            Ok(List(list_from_slice(&names)))
+        }),
+    );
+}
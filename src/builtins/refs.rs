@@ -0,0 +1,61 @@
+use crate::error::LispError;
+use crate::eval::eval_many;
+use crate::exp::*;
+use crate::stm::{dosync, Ref};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn ref_arg(arg: &Exp, name: &str) -> Result<Arc<Ref>, LispErr> {
+    match arg {
+        Object(object) => object.downcast::<Ref>(),
+        _ => None,
+    }
+    .ok_or_else(|| LispError::type_error("ref", arg).with_context(name))
+}
+
+pub fn init(env: &mut HashMap<String, Exp>) {
+    env.insert(
+        "ref".into(),
+        Func(|args, _| match args {
+            [value] => Ok(Ref::new(value.clone()).to_exp()),
+            _ => Err(LispError::arity("ref", 1, args.len())),
+        }),
+    );
+
+    // The body can run more than once, so it shouldn't have side effects
+    // other than on refs.
+    env.insert(
+        "dosync".into(),
+        Special(|args, env| dosync(env, || eval_many(args, env))),
+    );
+
+    // (alter ref f args...) sets the ref to (f value args...).
+    env.insert(
+        "alter".into(),
+        Func(|args, env| {
+            let [reference, function, extra @ ..] = args else {
+                return Err(LispError::arity("alter", "at least 2", args.len()));
+            };
+            ref_arg(reference, "alter")?.alter(function, extra, env)
+        }),
+    );
+
+    env.insert(
+        "commute".into(),
+        Func(|args, env| {
+            let [reference, function, extra @ ..] = args else {
+                return Err(LispError::arity("commute", "at least 2", args.len()));
+            };
+            ref_arg(reference, "commute")?.commute(function, extra, env)
+        }),
+    );
+
+    env.insert(
+        "ref-set".into(),
+        Func(|args, _| match args {
+            [reference, value] => ref_arg(reference, "ref-set")?.ref_set(value.clone()),
+            _ => Err(LispError::arity("ref-set", 2, args.len())),
+        }),
+    );
+}
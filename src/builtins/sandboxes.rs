@@ -0,0 +1,43 @@
+use crate::error::LispError;
+use crate::exp::*;
+use crate::interpreter::PURE_BUILTINS;
+
+use std::collections::HashMap;
+
+pub fn init(env: &mut HashMap<String, Exp>) {
+    // (sandbox (name... :pure) body...) evaluates body in a new interpreter
+    // that only has the builtins and host functions named, :pure standing for
+    // PURE_BUILTINS. body sees none of the definitions or locals around it.
+    env.insert(
+        "sandbox".into(),
+        Special(|args, env| {
+            let [allowed, body @ ..] = args else {
+                return Err(LispError::arity("sandbox", "at least 1", 0));
+            };
+            let Vector(allowed) = allowed else {
+                return Err(LispError::type_error("list of names", allowed).with_context("sandbox"));
+            };
+            let mut names = vec![];
+            for name in allowed.iter() {
+                match name {
+                    Symbol(name) => names.push(name.name().to_string()),
+                    Keyword(keyword) if keyword.name() == "pure" => {
+                        names.extend(PURE_BUILTINS.iter().map(|name| name.to_string()))
+                    }
+                    other => {
+                        return Err(
+                            LispError::type_error("builtin name", other).with_context("sandbox")
+                        )
+                    }
+                }
+            }
+            let names: Vec<&str> = names.iter().map(String::as_str).collect();
+            let sandbox = env.interpreter().sandbox(&names);
+            let mut res = List(None);
+            for form in body {
+                res = sandbox.eval(form)?;
+            }
+            Ok(res)
+        }),
+    );
+}
@@ -0,0 +1,104 @@
+use crate::cancel::{self, CancelToken};
+use crate::error::{ErrorKind, LispError};
+use crate::eval::{eval, eval_many};
+use crate::exp::list::{dolist, list_from_slice};
+use crate::exp::*;
+use crate::thread::Thread;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+fn thread_arg<'a>(arg: &'a Exp, name: &str) -> Result<&'a Thread, LispErr> {
+    match arg {
+        Object(object) if object.is::<Thread>() => Ok(object.downcast_ref().unwrap()),
+        other => Err(LispError::type_error("thread", other).with_context(name)),
+    }
+}
+
+pub fn init(env: &mut HashMap<String, Exp>) {
+    env.insert(
+        "thread/spawn".into(),
+        Func(|args, env| match args {
+            [function @ (Lambda(_) | Func(_) | Native(_))] => {
+                let thread = Thread::spawn(function.clone(), env)?;
+                Ok(Object(Object::with_type_name("thread", thread)))
+            }
+            [other] => Err(LispError::type_error("function", other).with_context("thread/spawn")),
+            _ => Err(LispError::arity("thread/spawn", 1, args.len())),
+        }),
+    );
+
+    env.insert(
+        "thread/join".into(),
+        Func(|args, _| match args {
+            [thread] => thread_arg(thread, "thread/join")?.join(),
+            _ => Err(LispError::arity("thread/join", 1, args.len())),
+        }),
+    );
+
+    // Waits for all of them before raising the first error, so none is left
+    // running behind the caller's back.
+    env.insert(
+        "thread/join-all".into(),
+        Func(|args, _| {
+            let [List(list)] = args else {
+                return match args {
+                    [other] => {
+                        Err(LispError::type_error("list", other).with_context("thread/join-all"))
+                    }
+                    _ => Err(LispError::arity("thread/join-all", 1, args.len())),
+                };
+            };
+            let mut threads = vec![];
+            dolist(list, |arg| {
+                threads.push(thread_arg(arg, "thread/join-all")?.clone());
+                Ok(())
+            })?;
+            let results: Vec<Result<Exp, LispErr>> =
+                threads.iter().map(|thread| thread.join()).collect();
+            let values = results.into_iter().collect::<Result<Vec<Exp>, LispErr>>()?;
+            Ok(List(list_from_slice(&values)))
+        }),
+    );
+
+    env.insert(
+        "thread/cancel".into(),
+        Func(|args, _| match args {
+            [thread] => {
+                thread_arg(thread, "thread/cancel")?.cancel();
+                Ok(List(None))
+            }
+            _ => Err(LispError::arity("thread/cancel", 1, args.len())),
+        }),
+    );
+
+    // (with-timeout millis body...) stops body with a timeout error if it's
+    // still running after millis, at the next call of a Lisp function.
+    env.insert(
+        "with-timeout".into(),
+        Special(|args, env| {
+            let [millis, body @ ..] = args else {
+                return Err(LispError::arity("with-timeout", "at least 1", 0));
+            };
+            let timeout = match eval(millis, env)? {
+                Num(millis) if millis >= 0 => Duration::from_millis(millis as u64),
+                other => {
+                    return Err(LispError::type_error("non-negative integer", &other)
+                        .with_context("with-timeout"))
+                }
+            };
+            let token = CancelToken::child(Some(timeout));
+            match token.run(|| eval_many(body, env)) {
+                // Unless it's the code around that is being cancelled.
+                Err(err)
+                    if matches!(err.kind(), ErrorKind::Cancelled)
+                        && token.timed_out()
+                        && !cancel::current().is_some_and(|outer| outer.is_cancelled()) =>
+                {
+                    Err(LispError::new(ErrorKind::Timeout(timeout)))
+                }
+                res => res,
+            }
+        }),
+    );
+}
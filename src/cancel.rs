@@ -0,0 +1,162 @@
+use crate::error::{ErrorKind, LispError};
+use crate::exp::LispErr;
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Cooperative cancellation. Evaluation checks the token of its thread every
+// time it calls a Lisp function and stops with a cancelled error once the
+// token is set, so code in a loop of builtins alone runs to its end. The
+// token stays set, catching the error doesn't get the code much further.
+// Builtins that block wake up every now and then to check it as well.
+
+// How long a blocking wait sleeps before checking the token again.
+const POLL: Duration = Duration::from_millis(10);
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    deadline: Option<Instant>,
+    // Cancelling the outer token cancels this one too.
+    parent: Option<CancelToken>,
+    // Whether the deadlines of the outer tokens stop this one as well.
+    follows_deadlines: bool,
+}
+
+/// Stops the evaluations running with it, from any thread. Clones share the
+/// same state.
+///
+/// ```
+/// use lisp::CancelToken;
+///
+/// let interpreter = lisp::Interpreter::new();
+/// interpreter.eval_str("(defun forever () (dotimes 1000000000000 (lambda (i) i)))").unwrap();
+/// let token = CancelToken::new();
+/// let canceller = token.clone();
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_millis(10));
+///     canceller.cancel();
+/// });
+/// let err = token.run(|| interpreter.eval_str("(forever)")).unwrap_err();
+/// assert_eq!(err.to_string(), "Evaluation cancelled");
+/// ```
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<Inner>);
+
+thread_local! {
+    static CURRENT: RefCell<Option<CancelToken>> = const { RefCell::new(None) };
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// A token that is cancelled along with the one of this thread, and
+    /// once timeout has passed if there is one.
+    pub fn child(timeout: Option<Duration>) -> CancelToken {
+        CancelToken(Arc::new(Inner {
+            cancelled: AtomicBool::new(false),
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            parent: current(),
+            follows_deadlines: true,
+        }))
+    }
+
+    /// A token for work spawned from this thread, which is cancelled along
+    /// with the one of this thread but outlives its deadlines, so a thread
+    /// started inside with-timeout isn't stopped when the form returns.
+    pub(crate) fn spawned() -> CancelToken {
+        CancelToken(Arc::new(Inner {
+            cancelled: AtomicBool::new(false),
+            deadline: None,
+            parent: current(),
+            follows_deadlines: false,
+        }))
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Relaxed)
+            || self.timed_out()
+            || self.0.parent.as_ref().is_some_and(|parent| {
+                if self.0.follows_deadlines {
+                    parent.is_cancelled()
+                } else {
+                    parent.cancel_called()
+                }
+            })
+    }
+
+    // Whether cancel was called on this token or an outer one, deadlines
+    // aside.
+    fn cancel_called(&self) -> bool {
+        self.0.cancelled.load(Ordering::Relaxed)
+            || self
+                .0
+                .parent
+                .as_ref()
+                .is_some_and(CancelToken::cancel_called)
+    }
+
+    /// Whether the deadline of this token, not of an outer one, has passed.
+    pub fn timed_out(&self) -> bool {
+        self.0
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Runs f with evaluation on this thread checking this token.
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        with_current(Some(self.clone()), f)
+    }
+}
+
+/// The token evaluation on this thread checks, if any.
+pub fn current() -> Option<CancelToken> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+// Puts the token back when dropped.
+struct Restore(Option<CancelToken>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let saved = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = saved);
+    }
+}
+
+pub(crate) fn with_current<T>(token: Option<CancelToken>, f: impl FnOnce() -> T) -> T {
+    let _restore = Restore(CURRENT.with(|current| current.replace(token)));
+    f()
+}
+
+/// Runs f without the token of the code around it.
+pub(crate) fn isolated<T>(f: impl FnOnce() -> T) -> T {
+    with_current(None, f)
+}
+
+/// Fails with a cancelled error if the token of this thread is set.
+pub(crate) fn check() -> Result<(), LispErr> {
+    CURRENT.with(|current| match &*current.borrow() {
+        Some(token) if token.is_cancelled() => Err(LispError::new(ErrorKind::Cancelled)),
+        _ => Ok(()),
+    })
+}
+
+/// How long a wait until deadline, or forever without one, can sleep before
+/// it has to check the token of this thread again. None if it can sleep for
+/// as long as it takes.
+pub(crate) fn wait_slice(deadline: Option<Instant>) -> Option<Duration> {
+    let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    if current().is_none() {
+        return left;
+    }
+    Some(left.map_or(POLL, |left| left.min(POLL)))
+}
@@ -0,0 +1,199 @@
+use crate::cancel;
+use crate::exp::*;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+// A queue shared between threads. Receivers wait on a condition variable of
+// the channel, select waits on one of its own that every channel it watches
+// wakes up when something arrives or the channel is closed. Waiting stops
+// with an error when the code waiting is cancelled.
+
+#[derive(Default)]
+struct Wakeup {
+    woken: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl Wakeup {
+    fn wake(&self) {
+        *self.woken.lock().unwrap() = true;
+        self.cond.notify_one();
+    }
+
+    /// Waits to be woken, false if the deadline passed first.
+    fn wait(&self, deadline: Option<Instant>) -> Result<bool, LispErr> {
+        let mut woken = self.woken.lock().unwrap();
+        while !*woken {
+            cancel::check()?;
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(false);
+            }
+            woken = match cancel::wait_slice(deadline) {
+                Some(timeout) => self.cond.wait_timeout(woken, timeout).unwrap().0,
+                None => self.cond.wait(woken).unwrap(),
+            };
+        }
+        *woken = false;
+        Ok(true)
+    }
+}
+
+struct State {
+    queue: VecDeque<Exp>,
+    closed: bool,
+    selects: Vec<Arc<Wakeup>>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    capacity: Option<usize>,
+    readable: Condvar,
+    writable: Condvar,
+}
+
+/// What a receive got.
+pub enum Received {
+    Value(Exp),
+    /// Nothing arrived in time.
+    Empty,
+    /// Closed, and everything sent before was received.
+    Closed,
+}
+
+/// A channel, unbounded or holding at most capacity values. Clones refer to
+/// the same channel.
+#[derive(Clone)]
+pub struct Channel(Arc<Inner>);
+
+impl Channel {
+    pub fn new(capacity: Option<usize>) -> Channel {
+        Channel(Arc::new(Inner {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                closed: false,
+                selects: vec![],
+            }),
+            capacity,
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+        }))
+    }
+
+    /// Queues value, waiting for room if the channel is full.
+    pub fn send(&self, value: Exp) -> Result<(), LispErr> {
+        let inner = &self.0;
+        let mut state = inner.state.lock().unwrap();
+        while !state.closed && inner.capacity.is_some_and(|cap| state.queue.len() >= cap) {
+            cancel::check()?;
+            state = match cancel::wait_slice(None) {
+                Some(timeout) => inner.writable.wait_timeout(state, timeout).unwrap().0,
+                None => inner.writable.wait(state).unwrap(),
+            };
+        }
+        if state.closed {
+            return Err("send: the channel is closed".into());
+        }
+        state.queue.push_back(value);
+        inner.readable.notify_one();
+        for wakeup in &state.selects {
+            wakeup.wake();
+        }
+        Ok(())
+    }
+
+    /// Takes the oldest value, waiting for one until deadline, or forever
+    /// without one.
+    pub fn recv(&self, deadline: Option<Instant>) -> Result<Received, LispErr> {
+        let inner = &self.0;
+        let mut state = inner.state.lock().unwrap();
+        loop {
+            if let Some(value) = state.queue.pop_front() {
+                inner.writable.notify_one();
+                return Ok(Received::Value(value));
+            }
+            if state.closed {
+                return Ok(Received::Closed);
+            }
+            cancel::check()?;
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(Received::Empty);
+            }
+            state = match cancel::wait_slice(deadline) {
+                Some(timeout) => inner.readable.wait_timeout(state, timeout).unwrap().0,
+                None => inner.readable.wait(state).unwrap(),
+            };
+        }
+    }
+
+    /// Takes the oldest value if there is one, without waiting.
+    pub fn try_recv(&self) -> Received {
+        let mut state = self.0.state.lock().unwrap();
+        match state.queue.pop_front() {
+            Some(value) => {
+                self.0.writable.notify_one();
+                Received::Value(value)
+            }
+            None if state.closed => Received::Closed,
+            None => Received::Empty,
+        }
+    }
+
+    /// No more values can be sent. Those already queued can still be
+    /// received, then receivers get Closed.
+    pub fn close(&self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.closed = true;
+        self.0.readable.notify_all();
+        self.0.writable.notify_all();
+        for wakeup in state.selects.drain(..) {
+            wakeup.wake();
+        }
+    }
+
+    fn watch(&self, wakeup: &Arc<Wakeup>) {
+        self.0.state.lock().unwrap().selects.push(wakeup.clone());
+    }
+
+    fn unwatch(&self, wakeup: &Arc<Wakeup>) {
+        let mut state = self.0.state.lock().unwrap();
+        state.selects.retain(|other| !Arc::ptr_eq(other, wakeup));
+    }
+}
+
+/// Receives from whichever channel has a value or is closed first, trying
+/// them in order. Gives the index of the channel and what it got, or None if
+/// the deadline passed.
+pub fn select(
+    channels: &[Channel],
+    deadline: Option<Instant>,
+) -> Result<Option<(usize, Received)>, LispErr> {
+    let wakeup = Arc::new(Wakeup::default());
+    // Watching before looking means nothing sent in between goes unnoticed.
+    for channel in channels {
+        channel.watch(&wakeup);
+    }
+    let ready = loop {
+        let ready =
+            channels
+                .iter()
+                .enumerate()
+                .find_map(|(index, channel)| match channel.try_recv() {
+                    Received::Empty => None,
+                    received => Some((index, received)),
+                });
+        if ready.is_some() {
+            break Ok(ready);
+        }
+        match wakeup.wait(deadline) {
+            Ok(true) => continue,
+            Ok(false) => break Ok(None),
+            Err(err) => break Err(err),
+        }
+    };
+    for channel in channels {
+        channel.unwatch(&wakeup);
+    }
+    ready
+}
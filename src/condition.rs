@@ -0,0 +1,277 @@
+use crate::env::Env;
+use crate::error::{ErrorKind, LispError};
+use crate::eval::apply;
+use crate::exp::*;
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::LocalKey;
+
+// Handlers and restarts are dynamic, they belong to the thread that
+// established them and last while the form that did it is being evaluated.
+// Errors are signaled where they happen, before unwinding, so handlers can
+// pick a restart that is still active.
+
+#[derive(Clone)]
+pub(crate) enum Handler {
+    /// From handler-bind, called with the condition without unwinding.
+    Bind(Keyword, Exp),
+    /// From try, conditions it catches stop the search and unwind to it.
+    /// None catches everything.
+    Catch(Vec<Option<Keyword>>),
+}
+
+struct Restart {
+    id: u64,
+    name: Keyword,
+    params: Vec<Sym>,
+}
+
+/// A restart the debugger can choose, innermost first.
+#[derive(Clone, Debug)]
+pub struct RestartInfo {
+    pub name: Keyword,
+    pub params: Vec<Sym>,
+}
+
+/// Asked what to do about errors nothing handles while restarts are active.
+/// Returns the index of the restart to invoke and its arguments, or None to
+/// let the error unwind.
+pub type Debugger = Arc<
+    dyn Fn(
+            &crate::interpreter::Interpreter,
+            &LispError,
+            &[RestartInfo],
+        ) -> Option<(usize, Vec<Exp>)>
+        + Send
+        + Sync,
+>;
+
+thread_local! {
+    static HANDLERS: RefCell<Vec<Handler>> = const { RefCell::new(vec![]) };
+    static RESTARTS: RefCell<Vec<Restart>> = const { RefCell::new(vec![]) };
+}
+
+static NEXT_RESTART: AtomicU64 = AtomicU64::new(0);
+
+/// Whether a handler for kind applies to a condition of cond_kind. Every
+/// condition is an :error.
+pub(crate) fn kind_matches(kind: &Keyword, cond_kind: &Keyword) -> bool {
+    kind == cond_kind || kind.name() == "error"
+}
+
+/// Whether a catch for kind, or for any kind without one, takes conditions
+/// of cond_kind. Cancelling, timeouts and resource limits are meant to stop
+/// the code around the try too, so only a catch naming them takes them.
+pub(crate) fn catch_matches(kind: Option<&Keyword>, cond_kind: &Keyword) -> bool {
+    match kind {
+        Some(kind) if kind == cond_kind => true,
+        _ if matches!(cond_kind.name(), "cancelled" | "timeout" | "resource-limit") => false,
+        Some(kind) => kind_matches(kind, cond_kind),
+        None => true,
+    }
+}
+
+/// The kind of a condition value: the :kind of a map, :error for the rest.
+pub fn condition_kind(condition: &Exp) -> Keyword {
+    match condition {
+        Map(map) => match map.get(&Keyword(Keyword::intern("kind"))) {
+            Some(Keyword(kind)) => kind.clone(),
+            _ => Keyword::intern("error"),
+        },
+        _ => Keyword::intern("error"),
+    }
+}
+
+// Takes a stack back to its old length when dropped, so what a form pushed
+// is gone even if evaluating it panics.
+pub(crate) struct Restore<T: 'static> {
+    stack: &'static LocalKey<RefCell<Vec<T>>>,
+    len: usize,
+}
+
+impl<T> Restore<T> {
+    pub(crate) fn new(stack: &'static LocalKey<RefCell<Vec<T>>>) -> Restore<T> {
+        let len = stack.with(|stack| stack.borrow().len());
+        Restore { stack, len }
+    }
+}
+
+impl<T> Drop for Restore<T> {
+    fn drop(&mut self) {
+        self.stack
+            .with(|stack| stack.borrow_mut().truncate(self.len));
+    }
+}
+
+// Empties a stack until dropped, then puts back what it held.
+pub(crate) struct Aside<T: 'static> {
+    stack: &'static LocalKey<RefCell<Vec<T>>>,
+    saved: Vec<T>,
+}
+
+impl<T> Aside<T> {
+    pub(crate) fn new(stack: &'static LocalKey<RefCell<Vec<T>>>) -> Aside<T> {
+        let saved = stack.with(|stack| stack.take());
+        Aside { stack, saved }
+    }
+}
+
+impl<T> Drop for Aside<T> {
+    fn drop(&mut self) {
+        let saved = std::mem::take(&mut self.saved);
+        self.stack.with(|stack| *stack.borrow_mut() = saved);
+    }
+}
+
+/// Runs f without the handlers and restarts of the code around it, for work
+/// that only happens to run on this thread.
+pub(crate) fn isolated<T>(f: impl FnOnce() -> T) -> T {
+    let _handlers = Aside::new(&HANDLERS);
+    let _restarts = Aside::new(&RESTARTS);
+    f()
+}
+
+pub(crate) fn with_handlers<T>(handlers: Vec<Handler>, f: impl FnOnce() -> T) -> T {
+    let _restore = Restore::new(&HANDLERS);
+    HANDLERS.with(|stack| stack.borrow_mut().extend(handlers));
+    f()
+}
+
+pub enum Signaled {
+    /// No handler took control, the signaler carries on.
+    Declined,
+    /// A try catches it, the condition unwinds to there.
+    Caught,
+}
+
+/// Runs the handlers that apply to condition, innermost first. A handler
+/// runs with only the handlers outside its own in effect.
+pub fn signal(condition: &Exp, env: &Arc<Env>) -> Result<Signaled, LispErr> {
+    let cond_kind = condition_kind(condition);
+    let mut index = HANDLERS.with(|stack| stack.borrow().len());
+    while index > 0 {
+        index -= 1;
+        let Some(handler) = HANDLERS.with(|stack| stack.borrow().get(index).cloned()) else {
+            continue;
+        };
+        match handler {
+            Handler::Catch(kinds) => {
+                let caught = kinds
+                    .iter()
+                    .any(|kind| catch_matches(kind.as_ref(), &cond_kind));
+                if caught {
+                    return Ok(Signaled::Caught);
+                }
+            }
+            Handler::Bind(kind, function) if kind_matches(&kind, &cond_kind) => {
+                let inner = HANDLERS.with(|stack| stack.borrow_mut().split_off(index));
+                let res = apply(&function, vec![condition.clone()], env);
+                HANDLERS.with(|stack| stack.borrow_mut().extend(inner));
+                res?;
+            }
+            Handler::Bind(..) => {}
+        }
+    }
+    Ok(Signaled::Declined)
+}
+
+/// Gives the handlers a look at an error on its way up. Returns the error to
+/// keep unwinding with, which is another one if a handler or the debugger
+/// transferred control.
+pub(crate) fn signal_error(err: LispError, env: &Arc<Env>) -> LispError {
+    if err.is_signaled() || err.is_transfer() {
+        return err;
+    }
+    let err = err.mark_signaled();
+    match signal(&err.value(), env) {
+        Err(transfer) => transfer,
+        Ok(Signaled::Caught) => err,
+        Ok(Signaled::Declined) => match env.interpreter().debugger() {
+            Some(debugger) => {
+                let restarts = active_restarts();
+                if restarts.is_empty() {
+                    return err;
+                }
+                match debugger(env.interpreter(), &err, &restarts) {
+                    Some((index, args)) => match restarts.get(index) {
+                        Some(restart) => invoke_restart(&restart.name, args).unwrap_err(),
+                        None => err,
+                    },
+                    None => err,
+                }
+            }
+            None => err,
+        },
+    }
+}
+
+pub fn active_restarts() -> Vec<RestartInfo> {
+    RESTARTS.with(|stack| {
+        stack
+            .borrow()
+            .iter()
+            .rev()
+            .map(|restart| RestartInfo {
+                name: restart.name.clone(),
+                params: restart.params.clone(),
+            })
+            .collect()
+    })
+}
+
+/// Unwinds to the innermost active restart called name.
+pub fn invoke_restart(name: &Keyword, args: Vec<Exp>) -> Result<Exp, LispErr> {
+    let id = RESTARTS.with(|stack| {
+        let stack = stack.borrow();
+        let restart = stack.iter().rev().find(|restart| restart.name == *name)?;
+        Some(restart.id)
+    });
+    match id {
+        Some(id) => Err(LispError::new(ErrorKind::Restart {
+            id,
+            name: name.clone(),
+            args,
+        })),
+        None => Err(format!("No restart named {name} is active").into()),
+    }
+}
+
+/// Makes restarts with the given names and parameters available while f
+/// runs. Gives back the result of f, or which restart was invoked and with
+/// what arguments.
+pub(crate) fn with_restarts(
+    restarts: Vec<(Keyword, Vec<Sym>)>,
+    env: &Arc<Env>,
+    f: impl FnOnce() -> Result<Exp, LispErr>,
+) -> Result<Result<Exp, (usize, Vec<Exp>)>, LispErr> {
+    let count = restarts.len();
+    let first_id = NEXT_RESTART.fetch_add(count as u64, Ordering::Relaxed);
+    let restore = Restore::new(&RESTARTS);
+    RESTARTS.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        // Pushed backwards so the first clause is the innermost.
+        for (i, (name, params)) in restarts.into_iter().enumerate().rev() {
+            stack.push(Restart {
+                id: first_id + i as u64,
+                name,
+                params,
+            });
+        }
+    });
+    // Errors are signaled while the restarts can still be chosen.
+    let res = f().map_err(|err| signal_error(err, env));
+    drop(restore);
+    match res {
+        Ok(value) => Ok(Ok(value)),
+        Err(err) => match err.kind() {
+            ErrorKind::Restart { id, args, .. }
+                if (first_id..first_id + count as u64).contains(id) =>
+            {
+                Ok(Err(((id - first_id) as usize, args.clone())))
+            }
+            _ => Err(err),
+        },
+    }
+}
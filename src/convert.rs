@@ -0,0 +1,291 @@
+use crate::env::Env;
+use crate::error::LispError;
+use crate::exp::list::{dolist, list_from_slice};
+use crate::exp::object::short_type_name;
+use crate::exp::*;
+use crate::limits;
+
+use std::any::Any;
+use std::sync::Arc;
+
+// Conversions between Exp and Rust values, used to expose plain Rust
+// closures as Lisp functions with Interpreter::register_fn.
+
+/// Rust values that can be taken from a Lisp value.
+pub trait FromExp: Sized {
+    fn from_exp(exp: &Exp) -> Result<Self, LispErr>;
+}
+
+/// Rust values that can be handed to Lisp.
+pub trait IntoExp {
+    fn into_exp(self) -> Exp;
+}
+
+impl FromExp for Exp {
+    fn from_exp(exp: &Exp) -> Result<Self, LispErr> {
+        Ok(exp.clone())
+    }
+}
+
+impl FromExp for i64 {
+    fn from_exp(exp: &Exp) -> Result<Self, LispErr> {
+        match exp {
+            Num(n) => Ok(*n),
+            other => Err(LispError::type_error("integer", other)),
+        }
+    }
+}
+
+impl FromExp for String {
+    fn from_exp(exp: &Exp) -> Result<Self, LispErr> {
+        match exp {
+            Str(s) => Ok(s.clone()),
+            other => Err(LispError::type_error("string", other)),
+        }
+    }
+}
+
+impl FromExp for char {
+    fn from_exp(exp: &Exp) -> Result<Self, LispErr> {
+        match exp {
+            Char(c) => Ok(*c),
+            other => Err(LispError::type_error("character", other)),
+        }
+    }
+}
+
+/// Anything is a bool in a condition, so this follows to_bool.
+impl FromExp for bool {
+    fn from_exp(exp: &Exp) -> Result<Self, LispErr> {
+        Ok(to_bool(exp))
+    }
+}
+
+impl FromExp for Keyword {
+    fn from_exp(exp: &Exp) -> Result<Self, LispErr> {
+        match exp {
+            Keyword(k) => Ok(k.clone()),
+            other => Err(LispError::type_error("keyword", other)),
+        }
+    }
+}
+
+impl FromExp for Map {
+    fn from_exp(exp: &Exp) -> Result<Self, LispErr> {
+        match exp {
+            Map(map) => Ok(map.clone()),
+            List(None) => Ok(Map::new()),
+            other => Err(LispError::type_error("map", other)),
+        }
+    }
+}
+
+/// Nil is None, anything else has to convert to T.
+impl<T: FromExp> FromExp for Option<T> {
+    fn from_exp(exp: &Exp) -> Result<Self, LispErr> {
+        match exp {
+            List(None) => Ok(None),
+            other => T::from_exp(other).map(Some),
+        }
+    }
+}
+
+fn elements(exp: &Exp) -> Result<Vec<Exp>, LispErr> {
+    match exp {
+        List(list) => {
+            let mut items = vec![];
+            dolist(list, |item| {
+                items.push(item.clone());
+                Ok(())
+            })?;
+            Ok(items)
+        }
+        Vector(items) => Ok(items.to_vec()),
+        other => Err(LispError::type_error("list", other)),
+    }
+}
+
+/// Host objects are taken out of Lisp as the Arc they were stored in.
+impl<T: Any + Send + Sync> FromExp for Arc<T> {
+    fn from_exp(exp: &Exp) -> Result<Self, LispErr> {
+        match exp {
+            Object(object) => object
+                .downcast::<T>()
+                .ok_or_else(|| LispError::type_error(short_type_name::<T>(), exp)),
+            other => Err(LispError::type_error(short_type_name::<T>(), other)),
+        }
+    }
+}
+
+impl<T: FromExp> FromExp for Vec<T> {
+    fn from_exp(exp: &Exp) -> Result<Self, LispErr> {
+        elements(exp)?.iter().map(T::from_exp).collect()
+    }
+}
+
+impl IntoExp for Exp {
+    fn into_exp(self) -> Exp {
+        self
+    }
+}
+
+impl IntoExp for i64 {
+    fn into_exp(self) -> Exp {
+        Num(self)
+    }
+}
+
+impl IntoExp for String {
+    fn into_exp(self) -> Exp {
+        limits::allocate(self.len());
+        Str(self)
+    }
+}
+
+impl IntoExp for &str {
+    fn into_exp(self) -> Exp {
+        limits::allocate(self.len());
+        Str(self.to_string())
+    }
+}
+
+impl IntoExp for char {
+    fn into_exp(self) -> Exp {
+        Char(self)
+    }
+}
+
+impl IntoExp for bool {
+    fn into_exp(self) -> Exp {
+        Bool(self)
+    }
+}
+
+impl IntoExp for Keyword {
+    fn into_exp(self) -> Exp {
+        Keyword(self)
+    }
+}
+
+impl IntoExp for Map {
+    fn into_exp(self) -> Exp {
+        Map(self)
+    }
+}
+
+impl IntoExp for Object {
+    fn into_exp(self) -> Exp {
+        Object(self)
+    }
+}
+
+impl IntoExp for () {
+    fn into_exp(self) -> Exp {
+        List(None)
+    }
+}
+
+impl<T: IntoExp> IntoExp for Option<T> {
+    fn into_exp(self) -> Exp {
+        match self {
+            Some(value) => value.into_exp(),
+            None => List(None),
+        }
+    }
+}
+
+impl<T: IntoExp> IntoExp for Vec<T> {
+    fn into_exp(self) -> Exp {
+        let items: Vec<Exp> = self.into_iter().map(IntoExp::into_exp).collect();
+        List(list_from_slice(&items))
+    }
+}
+
+// Tuples are lists with a fixed number of elements.
+macro_rules! tuple_conversions {
+    ($len:expr; $($name:ident $index:tt),+) => {
+        impl<$($name: FromExp),+> FromExp for ($($name,)+) {
+            fn from_exp(exp: &Exp) -> Result<Self, LispErr> {
+                let items = elements(exp)?;
+                if items.len() != $len {
+                    return Err(format!("expected a list of {} elements, got {exp}", $len).into());
+                }
+                Ok(($($name::from_exp(&items[$index])?,)+))
+            }
+        }
+
+        impl<$($name: IntoExp),+> IntoExp for ($($name,)+) {
+            fn into_exp(self) -> Exp {
+                List(list_from_slice(&[$(self.$index.into_exp()),+]))
+            }
+        }
+    };
+}
+
+tuple_conversions!(1; A 0);
+tuple_conversions!(2; A 0, B 1);
+tuple_conversions!(3; A 0, B 1, C 2);
+tuple_conversions!(4; A 0, B 1, C 2, D 3);
+
+/// What a registered closure may return: a value, or a Result of one.
+pub trait IntoLispResult {
+    fn into_lisp_result(self) -> Result<Exp, LispErr>;
+}
+
+impl<T: IntoExp> IntoLispResult for T {
+    fn into_lisp_result(self) -> Result<Exp, LispErr> {
+        Ok(self.into_exp())
+    }
+}
+
+impl<T: IntoExp> IntoLispResult for Result<T, LispErr> {
+    fn into_lisp_result(self) -> Result<Exp, LispErr> {
+        self.map(IntoExp::into_exp)
+    }
+}
+
+/// Rust closures that can become a Native function. Args is the tuple of
+/// argument types, it only exists to tell the implementations apart.
+pub trait IntoNative<Args> {
+    fn into_native(self, name: &str) -> Native;
+}
+
+/// Converts argument number index (from 0) of the function name.
+pub fn arg<T: FromExp>(name: &str, args: &[Exp], index: usize) -> Result<T, LispErr> {
+    T::from_exp(&args[index])
+        .map_err(|err| err.with_context(format!("{name} argument {}", index + 1)))
+}
+
+fn check_arity(name: &str, args: &[Exp], arity: usize) -> Result<(), LispErr> {
+    if args.len() != arity {
+        return Err(LispError::arity(name, arity, args.len()));
+    }
+    Ok(())
+}
+
+macro_rules! into_native {
+    ($arity:expr; $($name:ident $index:tt),*) => {
+        impl<Func, Ret, $($name),*> IntoNative<($($name,)*)> for Func
+        where
+            Func: Fn($($name),*) -> Ret + Send + Sync + 'static,
+            Ret: IntoLispResult,
+            $($name: FromExp,)*
+        {
+            #[allow(unused_variables)]
+            fn into_native(self, name: &str) -> Native {
+                let fn_name: Arc<str> = Arc::from(name);
+                Native::new(name, move |args: &[Exp], _: &Arc<Env>| {
+                    check_arity(&fn_name, args, $arity)?;
+                    self($(arg::<$name>(&fn_name, args, $index)?),*).into_lisp_result()
+                })
+            }
+        }
+    };
+}
+
+into_native!(0;);
+into_native!(1; A 0);
+into_native!(2; A 0, B 1);
+into_native!(3; A 0, B 1, C 2);
+into_native!(4; A 0, B 1, C 2, D 3);
+into_native!(5; A 0, B 1, C 2, D 3, E 4);
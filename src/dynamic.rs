@@ -0,0 +1,60 @@
+use crate::condition::{Aside, Restore};
+use crate::env::Global;
+use crate::exp::*;
+
+use std::cell::RefCell;
+use std::sync::Arc;
+
+// Dynamic variables are globals that binding can give another value for as
+// long as a body runs. Bindings belong to the thread that made them, other
+// threads keep seeing the global value.
+
+thread_local! {
+    // Innermost last.
+    static BINDINGS: RefCell<Vec<(Arc<Global>, Exp)>> = const { RefCell::new(vec![]) };
+}
+
+/// The value variable is bound to on this thread, if it is.
+pub(crate) fn lookup(variable: &Global) -> Option<Exp> {
+    BINDINGS.with(|bindings| {
+        bindings
+            .borrow()
+            .iter()
+            .rev()
+            .find(|(bound, _)| std::ptr::eq(Arc::as_ptr(bound), variable))
+            .map(|(_, value)| value.clone())
+    })
+}
+
+/// Gives the innermost binding of variable on this thread the value, if
+/// there is one. Returns whether there was.
+pub(crate) fn set(variable: &Global, value: &Exp) -> bool {
+    BINDINGS.with(|bindings| {
+        let mut bindings = bindings.borrow_mut();
+        let binding = bindings
+            .iter_mut()
+            .rev()
+            .find(|(bound, _)| std::ptr::eq(Arc::as_ptr(bound), variable));
+        match binding {
+            Some((_, bound)) => {
+                *bound = value.clone();
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Runs f with the variables bound to the values, until it returns or
+/// unwinds.
+pub fn with_bindings<T>(bindings: Vec<(Arc<Global>, Exp)>, f: impl FnOnce() -> T) -> T {
+    let _restore = Restore::new(&BINDINGS);
+    BINDINGS.with(|stack| stack.borrow_mut().extend(bindings));
+    f()
+}
+
+/// Runs f without the bindings of the code around it.
+pub(crate) fn isolated<T>(f: impl FnOnce() -> T) -> T {
+    let _bindings = Aside::new(&BINDINGS);
+    f()
+}
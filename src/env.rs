@@ -1,70 +1,210 @@
+use crate::builtins;
+use crate::dynamic;
+use crate::error::{ErrorKind, LispError};
 use crate::eval::{eval, eval_many, eval_lambda_call};
 use crate::exp::Lambda;
 use crate::exp::*;
+use crate::interpreter::Interpreter;
 
 use crate::exp::list::list_from_slice;
 
-use std::vec::Vec;
-
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use arc_swap::ArcSwapOption;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Env {
-    pub local: HashMap<String, Exp>,
-    upper: Option<Arc<Env>>, 
-                             
+    // Frames are flat, the resolver turns references to these into
+    // Address::Local(depth, index) so only unresolved code looks names up.
+    names: Vec<Sym>,
+    values: Vec<Exp>,
+    upper: Option<Arc<Env>>,
+    // Where globals are looked up for code running in this frame.
+    interpreter: Interpreter,
 }
 
 impl Env {
-    pub fn new() -> Self {
+    /// An empty toplevel environment for the given interpreter.
+    pub(crate) fn new(interpreter: &Interpreter) -> Self {
         Self {
-            local: HashMap::new(),
+            names: vec![],
+            values: vec![],
             upper: None,
+            interpreter: interpreter.clone(),
         }
     }
 
-    pub fn from_upper(upper: &Arc<Env>) -> Self {
+    pub(crate) fn from_upper(upper: &Arc<Env>) -> Self {
         Self {
-            local: HashMap::new(),
+            names: vec![],
+            values: vec![],
             upper: Some(Arc::clone(upper)),
+            interpreter: upper.interpreter.clone(),
         }
     }
 
-    pub fn insert(&mut self, symbol: &str, val: Exp) {
-        self.local.insert(symbol.to_string(), val);
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
     }
 
-    pub fn get(&self, symbol: &str) -> Result<Exp, LispErr> {
-        match self.local.get(symbol) {
-            Some(exp) => Ok(exp.clone()),
-            None => {
-                if let Some(upper) = &self.upper {
-                    upper.get(symbol)
-                } else {
-                    let toplevel = TOPLEVEL.read().unwrap();
-                    match toplevel.get(symbol) {
-                        Some(exp) => Ok(exp.clone()),
-                        None => Err(format!("Symbol {symbol} is unbound").into()),
-                    }
-                }
+    pub(crate) fn insert(&mut self, symbol: Sym, val: Exp) {
+        symbol.mark_bound_locally();
+        self.names.push(symbol);
+        self.values.push(val);
+    }
+
+    pub(crate) fn values(&self) -> &[Exp] {
+        &self.values
+    }
+
+    pub fn get(&self, symbol: Sym) -> Result<Exp, LispErr> {
+        match self.find(symbol) {
+            Some(exp) => Ok(exp),
+            None => self.interpreter.get_global(symbol),
+        }
+    }
+
+    /// Like get for a free variable that has a global cell, so it only
+    /// falls back to the cell when no frame binds the name. Names no frame
+    /// has ever bound go to the cell without looking.
+    pub(crate) fn get_free(&self, cell: &Global) -> Result<Exp, LispErr> {
+        if !cell.name().may_be_bound_locally() {
+            return self.interpreter.read_global(cell);
+        }
+        match self.find(cell.name()) {
+            Some(exp) => Ok(exp),
+            None => self.interpreter.read_global(cell),
+        }
+    }
+
+    fn find(&self, symbol: Sym) -> Option<Exp> {
+        let mut env = self;
+        loop {
+            // Later bindings shadow earlier ones with the same name.
+            if let Some(index) = env.names.iter().rposition(|name| *name == symbol) {
+                return Some(env.values[index].clone());
+            }
+            env = env.upper.as_ref()?;
+        }
+    }
+
+    pub(crate) fn get_local(&self, depth: u32, index: u32) -> Result<Exp, LispErr> {
+        let mut env = self;
+        for _ in 0..depth {
+            env = env
+                .upper
+                .as_ref()
+                .ok_or("Invalid local variable reference")?;
+        }
+        env.values
+            .get(index as usize)
+            .cloned()
+            .ok_or_else(|| "Invalid local variable reference".into())
+    }
+}
+
+/// Storage for one global variable of one interpreter. Resolved code holds
+/// on to the cell, so later definitions are seen without a table lookup.
+/// Definitions replace the value atomically and reading never waits.
+pub(crate) struct Global {
+    name: Sym,
+    owner: u64,
+    value: ArcSwapOption<Exp>,
+    // Declared with defvar or defparameter, so binding can rebind it.
+    dynamic: AtomicBool,
+    // A builtin left out of a sandbox. The code can still define it itself,
+    // but sandboxes it makes don't get the builtin back.
+    denied: bool,
+}
+
+impl Global {
+    pub(crate) fn new(name: Sym, owner: u64, value: Option<Exp>) -> Global {
+        Global {
+            name,
+            owner,
+            value: ArcSwapOption::new(value.map(Arc::new)),
+            dynamic: AtomicBool::new(false),
+            denied: false,
+        }
+    }
+
+    /// A cell that fails with a permission error when read while unbound.
+    pub(crate) fn denied(name: Sym, owner: u64, value: Option<Exp>) -> Global {
+        Global {
+            denied: true,
+            ..Global::new(name, owner, value)
+        }
+    }
+
+    pub fn name(&self) -> Sym {
+        self.name
+    }
+
+    /// Id of the interpreter this cell belongs to.
+    pub fn owner(&self) -> u64 {
+        self.owner
+    }
+
+    /// The value bound on this thread for dynamic variables, the global
+    /// value otherwise.
+    pub fn get(&self) -> Result<Exp, LispErr> {
+        if self.is_dynamic() {
+            if let Some(exp) = dynamic::lookup(self) {
+                return Ok(exp);
             }
         }
+        match &*self.value.load() {
+            Some(exp) => Ok(Exp::clone(exp)),
+            None if self.denied => Err(LispError::new(ErrorKind::Permission(self.name))),
+            None => Err(LispError::unbound(self.name)),
+        }
+    }
+
+    pub fn is_denied(&self) -> bool {
+        self.denied
+    }
+
+    pub fn peek(&self) -> Option<Exp> {
+        self.value.load().as_deref().cloned()
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        self.dynamic.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn make_dynamic(&self) {
+        self.dynamic.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set(&self, val: Exp) {
+        self.value.store(Some(Arc::new(val)));
     }
 }
 
-use lazy_static::lazy_static;
+impl fmt::Debug for Global {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
 
-lazy_static! {
-    pub static ref TOPLEVEL: RwLock<HashMap<String, Exp>> = RwLock::new(init_toplevel());
+// The error for arithmetic that doesn't fit in an integer.
+fn overflow(name: &str) -> LispErr {
+    format!("{name}: integer overflow").into()
 }
 
-pub fn set_global(sym: &Exp, val: &Exp) -> Result<(), LispErr> {
+/// Evaluates val at toplevel and stores it in the global sym, or in its
+/// innermost binding on this thread for a dynamic variable.
+pub fn set_global(sym: &Exp, val: &Exp, env: &Arc<Env>) -> Result<(), LispErr> {
     if let Symbol(place) = sym {
-        let evaled = eval(val, &Arc::new(Env::new()))?;
-        {
-            let mut env = TOPLEVEL.write().unwrap();
-            env.insert(place.to_string(), evaled);
+        let interpreter = env.interpreter();
+        let evaled = eval(val, &Arc::new(Env::new(interpreter)))?;
+        let cell = interpreter.global_cell(*place);
+        // Inside binding, a dynamic variable gets the value where it's bound.
+        if !(cell.is_dynamic() && dynamic::set(&cell, &evaled)) {
+            cell.set(evaled);
         }
         Ok(())
     } else {
@@ -72,14 +212,14 @@ pub fn set_global(sym: &Exp, val: &Exp) -> Result<(), LispErr> {
     }
 }
 
-fn init_toplevel() -> HashMap<String, Exp> {
+pub(crate) fn init_toplevel() -> HashMap<String, Exp> {
     let mut env = HashMap::new();
     env.insert(
         "+".into(),
         Func(|args, _| {
-            let x = args.iter().try_fold(0, |acc, x| match x {
-                Num(n) => Ok::<i64, LispErr>(acc + n),
-                _ => Err(format!("invalid + argument: {}", x).into()),
+            let x = args.iter().try_fold(0i64, |acc, x| match x {
+                Num(n) => acc.checked_add(*n).ok_or_else(|| overflow("+")),
+                _ => Err(LispError::type_error("integer", x).with_context("+")),
             })?;
 
             Ok(Num(x))
@@ -92,16 +232,16 @@ fn init_toplevel() -> HashMap<String, Exp> {
             if let Some((first, rest)) = args.split_first() {
                 let first = match first {
                     Num(n) => n,
-                    _ => return Err("- arg is not num".into()),
+                    other => return Err(LispError::type_error("integer", other).with_context("-")),
                 };
 
                 let x = rest.iter().try_fold(*first, |acc, x| match x {
-                    Num(n) => Ok::<i64, LispErr>(acc - n),
-                    _ => Err("invalid - argument".into()),
+                    Num(n) => acc.checked_sub(*n).ok_or_else(|| overflow("-")),
+                    _ => Err(LispError::type_error("integer", x).with_context("-")),
                 })?;
                 Ok(Num(x))
             } else {
-                Err("invalid arguments to -".into())
+                Err(LispError::arity("-", "at least 1", 0))
             }
         }),
     );
@@ -113,9 +253,9 @@ fn init_toplevel() -> HashMap<String, Exp> {
     env.insert(
         "*".into(),
         Func(|args, _| {
-            let x = args.iter().try_fold(1, |acc, x| match x {
-                Num(n) => Ok::<i64, LispErr>(acc * n),
-                _ => Err("invalid + argument".into()),
+            let x = args.iter().try_fold(1i64, |acc, x| match x {
+                Num(n) => acc.checked_mul(*n).ok_or_else(|| overflow("*")),
+                _ => Err(LispError::type_error("integer", x).with_context("*")),
             })?;
 
             Ok(Num(x))
@@ -128,33 +268,17 @@ fn init_toplevel() -> HashMap<String, Exp> {
             for arg in args.iter() {
                 println!("{arg}");
             }
-            Ok(Vector(vec![]))
+            Ok(Vector(Form::default()))
         }),
     );
 
     env.insert("progn".into(), Macro(|args, _| Ok(args.to_vec())));
 
-    env.insert(
-        "thread/spawn".into(),
-        Func(|args, env| {
-            use std::thread;
-            let Lambda(lambda) = &args[0] else {
-                return Err("thread/spawn param is not a function".into());
-            };
-            let lambda = lambda.clone();
-            let env = env.clone();
-            thread::spawn(move || {
-                let _ = eval_lambda_call(&lambda, &vec![], &env);
-            });
-            Ok(List(None))
-        })
-    );
-
     env.insert(
         "dotimes".into(),
         Func(|args, env| {
             if args.len() != 2 {
-                return Err("wrong number of arguments to dotimes".into());
+                return Err(LispError::arity("dotimes", 2, args.len()));
             }
             let Num(times) = &args[0] else {
                 return Err("dotimes first param is not a number".into());
@@ -163,7 +287,7 @@ fn init_toplevel() -> HashMap<String, Exp> {
                 return Err("dotimes second param is not a function".into());
             };
             for i in 0..*times {
-                eval_lambda_call(function, &vec![Num(i)], env)?;
+                eval_lambda_call(function, &[Num(i)], env)?;
             }
             Ok(List(None))
         })
@@ -171,11 +295,11 @@ fn init_toplevel() -> HashMap<String, Exp> {
 
     env.insert(
         "def".into(),
-        Macro(|args, _| {
+        Macro(|args, env| {
             if args.len() != 2 {
-                return Err("Wrong number of arguments to def".into());
+                return Err(LispError::arity("def", 2, args.len()));
             }
-            set_global(&args[0], &args[1])?;
+            set_global(&args[0], &args[1], env)?;
             Ok(vec![])
         }),
     );
@@ -204,7 +328,7 @@ fn init_toplevel() -> HashMap<String, Exp> {
                 return Err("Let cannot bind value to a non-symbol".into());
             };
             let value = eval(&binding[1], upper_env)?;
-            let_env.insert(name, value);
+            let_env.insert(*name, value);
         }
 
         let let_env = Arc::new(let_env);
@@ -213,24 +337,13 @@ fn init_toplevel() -> HashMap<String, Exp> {
 
     env.insert(
         "let".into(),
-        Macro(|args, env| {
+        Special(|args, env| {
             if args.len() < 2 {
-                return Err("Not enough arguments passed to let".into());
+                return Err(LispError::arity("let", "at least 2", args.len()));
             }
             let bindings = &args[0];
             let body = &args[1..];
-            let res = run_in_let(bindings, body, env)?;
-            Ok(vec![res])
-        }),
-    );
-
-    env.insert(
-        "assert".into(),
-        Func(|args, _| {
-            for x in args {
-                assert!(to_bool(x));
-            }
-            Ok(List(None))
+            run_in_let(bindings, body, env)
         }),
     );
 
@@ -238,7 +351,7 @@ fn init_toplevel() -> HashMap<String, Exp> {
         "cons".into(),
         Func(|args, _| {
             if args.len() != 2 {
-                return Err("Wrong arguments to cons".into());
+                return Err(LispError::arity("cons", 2, args.len()));
             }
             Ok(List(Some(Arc::new(Cons::new(
                 args[0].clone(),
@@ -253,13 +366,13 @@ fn init_toplevel() -> HashMap<String, Exp> {
         "car".into(),
         Func(|args, _| {
             if args.len() != 1 {
-                return Err("Wrong arguments to car".into());
+                return Err(LispError::arity("car", 1, args.len()));
             }
             match &args[0] {
                 // car of nil is nil
                 List(None) => Ok(List(None)),
                 List(Some(list)) => Ok(list.car.clone()),
-                _ => Err("car argument is not a list".into()),
+                other => Err(LispError::type_error("list", other).with_context("car")),
             }
         }),
     );
@@ -268,40 +381,39 @@ fn init_toplevel() -> HashMap<String, Exp> {
         "cdr".into(),
         Func(|args, _| {
             if args.len() != 1 {
-                return Err("Wrong arguments to cdr".into());
+                return Err(LispError::arity("cdr", 1, args.len()));
             }
             match &args[0] {
                 // cdr of nil is nil
                 List(None) => Ok(List(None)),
                 List(Some(list)) => Ok(list.cdr.clone()),
-                _ => Err("car argument is not a list".into()),
+                other => Err(LispError::type_error("list", other).with_context("cdr")),
             }
         }),
     );
 
     env.insert(
         "defun".into(),
-        Macro(|args, _| {
-            if let Vector(lambda_list) = &args[1] {
-                let mut llist: Vec<String> = vec![];
-                for arg in lambda_list {
-                    llist.push(match arg {
-                        Symbol(str) => str.clone(),
-                        _ => return Err("Invalid lambda list".into()),
-                    });
-                }
-                let body = Vec::from(&args[2..]);
-                set_global(&args[0], &Lambda(Lambda::new(llist, body)))?;
-                Ok(vec![])
-            } else {
-                return Err("Invalid lambda list".into());
+        Macro(|args, env| {
+            if args.len() < 2 {
+                return Err(LispError::arity("defun", "at least 2", args.len()));
             }
+            let name = match &args[0] {
+                Symbol(name) => Some(*name),
+                _ => None,
+            };
+            let lambda = Lambda::from_list(name, &args[1..], env.interpreter())?;
+            set_global(&args[0], &Lambda(lambda), env)?;
+            Ok(vec![])
         }),
     );
 
     env.insert(
         "if".into(),
         Macro(|args, env| {
+            if args.len() != 3 {
+                return Err(LispError::arity("if", 3, args.len()));
+            }
             let evaled = eval(&args[0], env)?;
             let is_true = to_bool(&evaled);
             if is_true {
@@ -316,12 +428,12 @@ fn init_toplevel() -> HashMap<String, Exp> {
         "or".into(),
         Func(|args, _| {
             if args.is_empty() {
-                return Err("or with empty args".into());
+                return Err(LispError::arity("or", "at least 1", 0));
             }
 
             for arg in args {
                 let x: bool = to_bool(arg);
-                if x == true {
+                if x {
                     return Ok(Bool(true));
                 }
             }
@@ -333,18 +445,18 @@ fn init_toplevel() -> HashMap<String, Exp> {
         "=".into(),
         Func(|args, _| {
             if args.is_empty() {
-                return Err("no arguments".into());
+                return Err(LispError::arity("=", "at least 1", 0));
             }
 
-            let first: i64 = match args[0] {
-                Num(n) => n,
-                _ => return Err("= arg is not a number".into()),
-            };
-
-            let equal = args.iter().all(|x| match x {
-                Num(n) => *n == first,
-                _ => panic!("= arg is not a number"),
-            });
+            let mut equal = true;
+            for arg in args {
+                match (arg, &args[0]) {
+                    (Num(n), Num(first)) => equal &= n == first,
+                    (Num(_), other) | (other, _) => {
+                        return Err(LispError::type_error("integer", other).with_context("="))
+                    }
+                }
+            }
 
             Ok(Bool(equal))
         }),
@@ -352,10 +464,36 @@ fn init_toplevel() -> HashMap<String, Exp> {
 
     env.insert(
         "lambda".into(),
-        Macro(|args, _| {
-            let lam = Lambda::from_list(args)?;
-            Ok(vec![Lambda(lam)])
+        Special(|args, env| {
+            if args.is_empty() {
+                return Err(LispError::arity("lambda", "at least 1", 0));
+            }
+            let lam = Lambda::from_list(None, args, env.interpreter())?;
+            Ok(Lambda(lam))
         }),
     );
+
+    env.insert(
+        "gensym".into(),
+        Func(|args, _| match args {
+            [] => Ok(Symbol(Sym::gensym("G__"))),
+            [Str(prefix)] => Ok(Symbol(Sym::gensym(prefix))),
+            [other] => Err(LispError::type_error("string", other).with_context("gensym")),
+            _ => Err(LispError::arity("gensym", "0 or 1", args.len())),
+        }),
+    );
+
+    builtins::chars::init(&mut env);
+    builtins::errors::init(&mut env);
+    builtins::conditions::init(&mut env);
+    builtins::maps::init(&mut env);
+    builtins::objects::init(&mut env);
+    builtins::threads::init(&mut env);
+    builtins::channels::init(&mut env);
+    builtins::atoms::init(&mut env);
+    builtins::futures::init(&mut env);
+    builtins::refs::init(&mut env);
+    builtins::bindings::init(&mut env);
+    builtins::sandboxes::init(&mut env);
     env
 }
@@ -0,0 +1,389 @@
+use crate::backtrace::summarize;
+use crate::condition::condition_kind;
+use crate::exp::*;
+
+use std::any::Any;
+use std::fmt;
+use std::time::Duration;
+
+/// Where a form was read from, line and column both counted from 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Parse(String),
+    UnboundSymbol(Sym),
+    Type {
+        expected: String,
+        got: Exp,
+    },
+    Arity {
+        name: String,
+        expected: String,
+        got: usize,
+    },
+    /// Raised by the script itself, carrying any value.
+    User(Exp),
+    /// An assert that failed, with the source of the false expression.
+    Assertion(String),
+    /// Not an error, control going to the restart-case that made restart id.
+    Restart {
+        id: u64,
+        name: Keyword,
+        args: Vec<Exp>,
+    },
+    /// Not an error either, a dosync going back to retry its transaction.
+    Conflict,
+    /// The evaluation was stopped through its cancel token.
+    Cancelled,
+    /// A with-timeout body that took longer than it was given.
+    Timeout(Duration),
+    /// Evaluation used more of something than the interpreter's limits allow.
+    ResourceLimit {
+        resource: &'static str,
+        limit: u64,
+    },
+    /// A builtin that the sandbox running the code doesn't allow.
+    Permission(Sym),
+    Io(std::io::Error),
+    /// A builtin that panicked, with its name and the panic message.
+    Panic {
+        name: String,
+        message: String,
+    },
+    /// Everything that doesn't need to be told apart yet.
+    Other(String),
+}
+
+// io::Error can't be cloned, the copy keeps its kind and message.
+impl Clone for ErrorKind {
+    fn clone(&self) -> Self {
+        match self {
+            ErrorKind::Parse(message) => ErrorKind::Parse(message.clone()),
+            ErrorKind::UnboundSymbol(symbol) => ErrorKind::UnboundSymbol(*symbol),
+            ErrorKind::Type { expected, got } => ErrorKind::Type {
+                expected: expected.clone(),
+                got: got.clone(),
+            },
+            ErrorKind::Arity {
+                name,
+                expected,
+                got,
+            } => ErrorKind::Arity {
+                name: name.clone(),
+                expected: expected.clone(),
+                got: *got,
+            },
+            ErrorKind::User(value) => ErrorKind::User(value.clone()),
+            ErrorKind::Assertion(source) => ErrorKind::Assertion(source.clone()),
+            ErrorKind::Restart { id, name, args } => ErrorKind::Restart {
+                id: *id,
+                name: name.clone(),
+                args: args.clone(),
+            },
+            ErrorKind::Conflict => ErrorKind::Conflict,
+            ErrorKind::Cancelled => ErrorKind::Cancelled,
+            ErrorKind::Timeout(timeout) => ErrorKind::Timeout(*timeout),
+            ErrorKind::ResourceLimit { resource, limit } => ErrorKind::ResourceLimit {
+                resource,
+                limit: *limit,
+            },
+            ErrorKind::Permission(name) => ErrorKind::Permission(*name),
+            ErrorKind::Io(err) => ErrorKind::Io(std::io::Error::new(err.kind(), err.to_string())),
+            ErrorKind::Panic { name, message } => ErrorKind::Panic {
+                name: name.clone(),
+                message: message.clone(),
+            },
+            ErrorKind::Other(message) => ErrorKind::Other(message.clone()),
+        }
+    }
+}
+
+/// A call in a backtrace.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub name: String,
+    /// The arguments, shortened.
+    pub args: String,
+    pub span: Option<Span>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.args.as_str() {
+            "" => write!(f, "({})", self.name)?,
+            args => write!(f, "({} {args})", self.name)?,
+        }
+        if let Some(span) = self.span {
+            write!(f, " at {span}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Error of anything that reads or evaluates Lisp code. Display gives the
+/// message alone, report adds where it happened.
+#[derive(Clone)]
+pub struct LispError(Box<Inner>);
+
+#[derive(Clone)]
+struct Inner {
+    kind: ErrorKind,
+    // What was being done, e.g. "add argument 2", shown before the message.
+    context: Option<String>,
+    span: Option<Span>,
+    backtrace: Vec<Frame>,
+    // Handlers have already seen it.
+    signaled: bool,
+}
+
+impl LispError {
+    pub fn new(kind: ErrorKind) -> LispError {
+        LispError(Box::new(Inner {
+            kind,
+            context: None,
+            span: None,
+            backtrace: vec![],
+            signaled: false,
+        }))
+    }
+
+    pub fn parse(message: impl Into<String>, span: Option<Span>) -> LispError {
+        LispError::new(ErrorKind::Parse(message.into())).with_span(span)
+    }
+
+    pub fn unbound(symbol: Sym) -> LispError {
+        LispError::new(ErrorKind::UnboundSymbol(symbol))
+    }
+
+    pub fn type_error(expected: impl Into<String>, got: &Exp) -> LispError {
+        LispError::new(ErrorKind::Type {
+            expected: expected.into(),
+            got: got.clone(),
+        })
+    }
+
+    pub fn arity(name: impl Into<String>, expected: impl ToString, got: usize) -> LispError {
+        LispError::new(ErrorKind::Arity {
+            name: name.into(),
+            expected: expected.to_string(),
+            got,
+        })
+    }
+
+    /// A panic caught while running name, from the payload it unwound with.
+    pub(crate) fn panic(name: &dyn fmt::Display, payload: Box<dyn Any + Send>) -> LispError {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown cause".into(),
+            },
+        };
+        LispError::new(ErrorKind::Panic {
+            name: name.to_string(),
+            message,
+        })
+    }
+
+    pub fn user(value: Exp) -> LispError {
+        LispError::new(ErrorKind::User(value))
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0.kind
+    }
+
+    /// The keyword catch clauses match against. Errors raised with a map
+    /// can pick their own through its :kind entry.
+    pub fn kind_keyword(&self) -> Keyword {
+        let name = match &self.0.kind {
+            ErrorKind::Parse(_) => "parse-error",
+            ErrorKind::UnboundSymbol(_) => "unbound-symbol",
+            ErrorKind::Type { .. } => "type-error",
+            ErrorKind::Arity { .. } => "arity-error",
+            ErrorKind::User(value) => return condition_kind(value),
+            ErrorKind::Other(_) => "error",
+            ErrorKind::Assertion(_) => "assertion-error",
+            ErrorKind::Restart { .. } => "restart",
+            ErrorKind::Conflict => "conflict",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::Timeout(_) => "timeout",
+            ErrorKind::ResourceLimit { .. } => "resource-limit",
+            ErrorKind::Permission(_) => "permission-error",
+            ErrorKind::Io(_) => "io-error",
+            ErrorKind::Panic { .. } => "panic",
+        };
+        Keyword::intern(name)
+    }
+
+    /// The condition handlers and catch clauses get: the raised value for
+    /// errors raised by the script, {:kind kind :message message} for the rest.
+    pub fn value(&self) -> Exp {
+        match &self.0.kind {
+            ErrorKind::User(value) => value.clone(),
+            _ => Map(Map::new()
+                .insert(
+                    Keyword(Keyword::intern("kind")),
+                    Keyword(self.kind_keyword()),
+                )
+                .insert(Keyword(Keyword::intern("message")), Str(self.to_string()))),
+        }
+    }
+
+    /// Whether this is control going somewhere rather than an error, which
+    /// handlers and try leave alone.
+    pub fn is_transfer(&self) -> bool {
+        matches!(self.0.kind, ErrorKind::Restart { .. } | ErrorKind::Conflict)
+    }
+
+    pub(crate) fn is_signaled(&self) -> bool {
+        self.0.signaled
+    }
+
+    pub(crate) fn mark_signaled(mut self) -> LispError {
+        self.0.signaled = true;
+        self
+    }
+
+    /// The same error for raising again somewhere else, where handlers
+    /// haven't seen it yet.
+    pub(crate) fn reraise(mut self) -> LispError {
+        self.0.signaled = false;
+        self
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.0.span
+    }
+
+    /// Innermost call first.
+    pub fn backtrace(&self) -> &[Frame] {
+        &self.0.backtrace
+    }
+
+    pub fn with_context(mut self, context: impl Into<String>) -> LispError {
+        self.0.context = Some(context.into());
+        self
+    }
+
+    /// Sets the span unless a more precise one is already known.
+    pub fn with_span(mut self, span: Option<Span>) -> LispError {
+        if self.0.span.is_none() {
+            self.0.span = span;
+        }
+        self
+    }
+
+    /// Adds a call the error unwound through, with the values it was called with.
+    pub(crate) fn push_frame(mut self, name: &Exp, args: &[Exp], span: Option<Span>) -> LispError {
+        self.0.backtrace.push(Frame {
+            name: name.to_string(),
+            args: summarize(args, Exp::to_source),
+            span,
+        });
+        self
+    }
+
+    /// The message followed by the location and the backtrace, for showing
+    /// errors nobody handled.
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        if let Some(span) = self.0.span {
+            report += &format!(" at {span}");
+        }
+        for frame in &self.0.backtrace {
+            report += &format!("\n  in {frame}");
+        }
+        report
+    }
+}
+
+impl fmt::Display for LispError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(context) = &self.0.context {
+            write!(f, "{context}: ")?;
+        }
+        match &self.0.kind {
+            ErrorKind::Parse(message) | ErrorKind::Other(message) => write!(f, "{message}"),
+            ErrorKind::UnboundSymbol(symbol) => write!(f, "Symbol {symbol} is unbound"),
+            ErrorKind::Type { expected, got } => {
+                write!(f, "expected {expected}, got {} {got}", got.type_name())
+            }
+            ErrorKind::Arity {
+                name,
+                expected,
+                got,
+            } => {
+                let plural = if expected == "1" { "" } else { "s" };
+                write!(f, "{name} expects {expected} argument{plural}, got {got}")
+            }
+            ErrorKind::User(value) => write!(f, "{value}"),
+            ErrorKind::Assertion(source) => write!(f, "Assertion failed: {source}"),
+            ErrorKind::Restart { name, .. } => write!(f, "Restart {name} invoked"),
+            ErrorKind::Conflict => write!(f, "Transaction conflict"),
+            ErrorKind::Cancelled => write!(f, "Evaluation cancelled"),
+            ErrorKind::Timeout(timeout) => write!(f, "Timed out after {}ms", timeout.as_millis()),
+            ErrorKind::ResourceLimit { resource, limit } => {
+                write!(f, "Resource limit exceeded: more than {limit} {resource}")
+            }
+            ErrorKind::Permission(name) => write!(f, "Permission denied: {name}"),
+            ErrorKind::Io(err) => write!(f, "{err}"),
+            ErrorKind::Panic { name, message } => write!(f, "{name} panicked: {message}"),
+        }
+    }
+}
+
+impl fmt::Debug for LispError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LispError")
+            .field("kind", &self.0.kind)
+            .field("context", &self.0.context)
+            .field("span", &self.0.span)
+            .field("backtrace", &self.0.backtrace)
+            .finish()
+    }
+}
+
+impl std::error::Error for LispError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.0.kind {
+            ErrorKind::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ErrorKind> for LispError {
+    fn from(kind: ErrorKind) -> Self {
+        LispError::new(kind)
+    }
+}
+
+impl From<String> for LispError {
+    fn from(message: String) -> Self {
+        LispError::new(ErrorKind::Other(message))
+    }
+}
+
+impl From<&str> for LispError {
+    fn from(message: &str) -> Self {
+        LispError::new(ErrorKind::Other(message.into()))
+    }
+}
+
+impl From<std::io::Error> for LispError {
+    fn from(err: std::io::Error) -> Self {
+        LispError::new(ErrorKind::Io(err))
+    }
+}
@@ -1,8 +1,17 @@
+use crate::backtrace;
+use crate::condition::signal_error;
 use crate::env::Env;
+use crate::error::LispError;
 use crate::exp::lambda::Lambda;
+use crate::exp::var::Address;
 use crate::exp::*;
+use crate::interpreter::builtin_name;
+use crate::limits;
 
-use std::sync::Arc;
+use std::cell::Cell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Once};
 
 pub fn eval_many(exps: &[Exp], env: &Arc<Env>) -> Result<Exp, LispErr> {
     if let Some((last, exps)) = exps.split_last() {
@@ -11,54 +20,159 @@ pub fn eval_many(exps: &[Exp], env: &Arc<Env>) -> Result<Exp, LispErr> {
         }
         eval(last, env)
     } else {
-        return Ok(Vector(vec![]));
+        Ok(Vector(Form::default()))
     }
 }
 
-fn eval_fun_call(func: NativeFunction, args: &[Exp], env: &Arc<Env>) -> Result<Exp, LispErr> {
-    let mut arg_list = vec![];
-    for exp in args {
-        arg_list.push(eval(exp, env)?);
+fn eval_args(args: &[Exp], env: &Arc<Env>) -> Result<Vec<Exp>, LispErr> {
+    let mut arg_list = Vec::with_capacity(args.len());
+    for arg in args {
+        arg_list.push(eval(arg, env)?);
     }
-    func(&arg_list, env)
+    Ok(arg_list)
 }
 
 pub fn eval_lambda_call(lambda: &Lambda, args: &[Exp], env: &Arc<Env>) -> Result<Exp, LispErr> {
-    let mut arg_list = vec![];
-    for arg in args {
-        arg_list.push(eval(arg, env)?);
+    lambda.call(eval_args(args, env)?, env)
+}
+
+/// Keywords called as functions look themselves up in a map: (:name m default)
+fn keyword_lookup(keyword: &Keyword, args: &[Exp]) -> Result<Exp, LispErr> {
+    let (coll, default) = match args {
+        [coll] => (coll, List(None)),
+        [coll, default] => (coll, default.clone()),
+        _ => return Err(format!("Wrong number of arguments to {keyword}").into()),
+    };
+    let key = Keyword(keyword.clone());
+    match coll {
+        Map(map) => Ok(map.get(&key).cloned().unwrap_or(default)),
+        Set(set) if set.contains(&key) => Ok(key),
+        Set(_) | List(None) => Ok(default),
+        _ => Err(format!("{keyword} cannot look itself up in {coll}").into()),
+    }
+}
+
+thread_local! {
+    // Guards running on this thread. Their panics become errors, so the panic
+    // hook doesn't print them.
+    static GUARDS: Cell<u32> = const { Cell::new(0) };
+}
+
+// Wraps the panic hook, once, so that it keeps quiet inside guard.
+fn quiet_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if GUARDS.with(Cell::get) == 0 {
+                hook(info);
+            }
+        }));
+    });
+}
+
+/// Runs a native function so that a panic in it becomes an error naming it
+/// instead of taking down the thread.
+fn guard<T>(
+    name: &dyn fmt::Display,
+    call: impl FnOnce() -> Result<T, LispErr>,
+) -> Result<T, LispErr> {
+    quiet_panic_hook();
+    GUARDS.with(|guards| guards.set(guards.get() + 1));
+    let res = panic::catch_unwind(AssertUnwindSafe(call));
+    GUARDS.with(|guards| guards.set(guards.get() - 1));
+    res.map_err(|payload| LispError::panic(name, payload))?
+}
+
+// Displays as the name of a builtin, only looked up when a panic needs it.
+struct BuiltinName(NativeFunction);
+
+impl fmt::Display for BuiltinName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match builtin_name(self.0) {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "builtin"),
+        }
+    }
+}
+
+/// Calls an already evaluated function value with already evaluated arguments.
+pub fn apply(func: &Exp, args: Vec<Exp>, env: &Arc<Env>) -> Result<Exp, LispErr> {
+    match func {
+        Func(fun) => guard(&BuiltinName(*fun), || fun(&args, env)),
+        Lambda(lambda) => lambda.call(args, env),
+        Keyword(keyword) => keyword_lookup(keyword, &args),
+        Native(native) => guard(&native.name(), || native.call(&args, env)),
+        other => Err(LispError::type_error("function", other)),
     }
-    lambda.call(arg_list, env)
 }
 
-fn eval_macro(macr: Macro, args: &[Exp], env: &Arc<Env>) -> Result<Exp, LispErr> {
-    let macroexpand = macr(args, env)?;
+fn eval_macro(name: &Exp, macr: Macro, args: &[Exp], env: &Arc<Env>) -> Result<Exp, LispErr> {
+    let macroexpand = guard(name, || macr(args, env))?;
     eval_many(&macroexpand, env)
 }
 
 pub fn eval(exp: &Exp, env: &Arc<Env>) -> Result<Exp, LispErr> {
     match exp {
         Num(num) => Ok(Num(*num)),
-        Symbol(sym) => env.get(sym),
+        Symbol(sym) => env.get(*sym),
         Str(string) => Ok(Str(string.clone())),
-        Vector(list) => {
-            if list.len() == 0 {
-                return Ok(Vector(vec![]));
-            }
+        Char(c) => Ok(Char(*c)),
+        // Values that only exist at runtime evaluate to themselves.
+        List(_) | Bool(_) | Func(_) | Macro(_) | Map(_) | Set(_) => Ok(exp.clone()),
+        Keyword(_) | Native(_) | Object(_) | Special(_) => Ok(exp.clone()),
+        Var(var) => match &var.0 {
+            Address::Local(depth, index, _) => env.get_local(*depth, *index),
+            Address::Global(cell) => env.get_free(cell),
+        },
+        // Errors remember the innermost form they came from.
+        Vector(form) => eval_form(form, env).map_err(|err| err.with_span(form.span())),
+        Lambda(lam) => Ok(Lambda(lam.clone())),
+    }
+}
 
-            let Symbol(first) = &list[0] else {
-                return Err("Expression cannot be evaluated as a function or macro".into());
-            };
+fn eval_form(list: &Form, env: &Arc<Env>) -> Result<Exp, LispErr> {
+    limits::step()?;
+    if list.is_empty() {
+        return Ok(Vector(Form::default()));
+    }
 
-            let rest = &list[1..];
-            match env.get(first)? {
-                Func(fun) => eval_fun_call(fun, rest, env),
-                Lambda(ref lambda) => eval_lambda_call(lambda, rest, env),
-                Macro(macr) => eval_macro(macr, rest, env),
-                _ => Err("Attempted to call non-callable object".into()),
-            }
+    let rest = &list[1..];
+    let head = match &list[0] {
+        Symbol(first) => env.get(*first)?,
+        Var(_) | Vector(_) => eval(&list[0], env)?,
+        Keyword(keyword) => {
+            let arg_list = eval_args(rest, env)?;
+            return keyword_lookup(keyword, &arg_list);
         }
-        Lambda(lam) => Ok(Lambda(lam.clone())),
-        _ => Err("wtf".into()),
+        other => return Err(LispError::type_error("function", other)),
+    };
+
+    // Errors are signaled at the innermost call they come out of, and each
+    // call they unwind through goes into their backtrace.
+    let frame =
+        |err: LispErr, args: &[Exp]| signal_error(err, env).push_frame(&list[0], args, list.span());
+    match head {
+        Func(fun) => {
+            let args = eval_args(rest, env)?;
+            guard(&list[0], || fun(&args, env)).map_err(|err| frame(err, &args))
+        }
+        Native(ref native) => {
+            let args = eval_args(rest, env)?;
+            guard(&list[0], || native.call(&args, env)).map_err(|err| frame(err, &args))
+        }
+        Lambda(ref lambda) => {
+            let args = eval_args(rest, env)?;
+            // Only Lisp functions go on the live stack, builtins are too
+            // frequent to pay for it and only show up in error backtraces.
+            let _call = backtrace::enter(list);
+            let lambda_env = lambda.bind(args, env).map_err(|err| frame(err, &[]))?;
+            lambda
+                .run(&lambda_env)
+                .map_err(|err| frame(err, lambda_env.values()))
+        }
+        Macro(macr) => eval_macro(&list[0], macr, rest, env),
+        Special(special) => guard(&list[0], || special(rest, env)),
+        _ => Err(LispError::type_error("function", &head)),
     }
 }
@@ -0,0 +1,29 @@
+const CHAR_NAMES: &[(&str, char)] = &[
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("nul", '\0'),
+    ("null", '\0'),
+    ("altmode", '\x1b'),
+    ("escape", '\x1b'),
+    ("backspace", '\x08'),
+    ("delete", '\x7f'),
+    ("alarm", '\x07'),
+];
+
+/// Character named by a #\name literal, e.g. "space" or "newline".
+pub fn char_from_name(name: &str) -> Option<char> {
+    CHAR_NAMES.iter().find(|(n, _)| *n == name).map(|(_, c)| *c)
+}
+
+/// Text that follows #\ when printing a character, so it reads back as itself.
+pub fn char_name(c: char) -> String {
+    if let Some((name, _)) = CHAR_NAMES.iter().find(|(_, ch)| *ch == c) {
+        name.to_string()
+    } else if c.is_control() {
+        format!("x{:x}", c as u32)
+    } else {
+        c.to_string()
+    }
+}
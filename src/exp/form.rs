@@ -0,0 +1,79 @@
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::error::Span;
+use crate::exp::Exp;
+
+/// The elements of a parenthesized form, and where the reader found it.
+/// Derefs to the elements, the span only matters for error messages. Cloning
+/// shares the elements, so the evaluator can keep forms on its call stack.
+#[derive(Clone, Debug, Default)]
+pub struct Form {
+    items: Arc<Vec<Exp>>,
+    span: Option<Span>,
+}
+
+impl Form {
+    pub fn new(items: Vec<Exp>, span: Option<Span>) -> Form {
+        Form {
+            items: Arc::new(items),
+            span,
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn into_vec(self) -> Vec<Exp> {
+        Arc::unwrap_or_clone(self.items)
+    }
+}
+
+impl Deref for Form {
+    type Target = Vec<Exp>;
+
+    fn deref(&self) -> &Vec<Exp> {
+        &self.items
+    }
+}
+
+impl From<Vec<Exp>> for Form {
+    fn from(items: Vec<Exp>) -> Self {
+        Form::new(items, None)
+    }
+}
+
+impl IntoIterator for Form {
+    type Item = Exp;
+    type IntoIter = std::vec::IntoIter<Exp>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_vec().into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Form {
+    type Item = &'a Exp;
+    type IntoIter = std::slice::Iter<'a, Exp>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+// Where a form was read doesn't change what it is.
+impl PartialEq for Form {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items
+    }
+}
+
+impl Eq for Form {}
+
+impl Hash for Form {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.items.hash(state);
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref KEYWORDS: Mutex<HashSet<Arc<str>>> = Mutex::new(HashSet::new());
+}
+
+/// Interned keyword name, there's only one allocation per distinct name so
+/// keywords are compared by pointer.
+#[derive(Clone)]
+pub struct Keyword(Arc<str>);
+
+impl Keyword {
+    pub fn intern(name: &str) -> Keyword {
+        let mut keywords = KEYWORDS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(existing) = keywords.get(name) {
+            return Keyword(existing.clone());
+        }
+        let name: Arc<str> = Arc::from(name);
+        keywords.insert(name.clone());
+        Keyword(name)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Keyword {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Keyword {}
+
+impl Hash for Keyword {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl fmt::Debug for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, ":{}", self.0)
+    }
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, ":{}", self.0)
+    }
+}
@@ -1,51 +1,193 @@
 use core::fmt;
 use std::iter::zip;
 
-use crate::{eval::eval_many, exp::*};
+use crate::{
+    cancel,
+    error::LispError,
+    eval::{eval, eval_many},
+    exp::*,
+    interpreter::Interpreter,
+    resolve::{resolve, resolve_many, Scope},
+};
 
+struct LambdaDef {
+    // Set by defun, for error messages.
+    name: Option<Sym>,
+    args: Vec<Sym>,
+    // &key parameters, with the expression used when the caller omits them.
+    keys: Vec<(Sym, Keyword, Option<Exp>)>,
+    body: Vec<Exp>,
+}
+
+/// A function. Each call runs the body in a new frame on top of the caller's
+/// environment, so variables it doesn't bind are looked up where it's called.
 #[derive(Clone)]
 pub struct Lambda {
-    args: Vec<String>,
-    body: Vec<Exp>,
+    def: Arc<LambdaDef>,
 }
 
 impl Lambda {
-    pub fn new(args: Vec<String>, body: Vec<Exp>) -> Lambda {
-        Lambda { args, body }
+    /// Globals of interpreter are used to resolve the body.
+    pub fn new(args: Vec<Sym>, body: Vec<Exp>, interpreter: &Interpreter) -> Lambda {
+        Lambda::build(None, args, vec![], &body, interpreter)
+    }
+
+    fn build(
+        name: Option<Sym>,
+        args: Vec<Sym>,
+        keys: Vec<(Sym, Keyword, Option<Exp>)>,
+        body: &[Exp],
+        interpreter: &Interpreter,
+    ) -> Lambda {
+        // Names of the frame built on each call: args followed by keys.
+        let frame: Vec<Sym> = args
+            .iter()
+            .copied()
+            .chain(keys.iter().map(|(name, _, _)| *name))
+            .collect();
+        let caller = Scope::Caller(interpreter);
+        // A default sees the args and the keys before it.
+        let keys = keys
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, keyword, default))| {
+                let scope = Scope::Frame(&frame[..args.len() + i], &caller);
+                (name, keyword, default.map(|d| resolve(&d, &scope)))
+            })
+            .collect();
+        let body = resolve_many(body, &Scope::Frame(&frame, &caller));
+        Lambda {
+            def: Arc::new(LambdaDef {
+                name,
+                args,
+                keys,
+                body,
+            }),
+        }
     }
 
-    pub fn from_list(args: &[Exp]) -> Result<Lambda, LispErr> {
-        if let Vector(lambda_list) = &args[0] {
-            let mut llist: Vec<String> = vec![];
+    /// Builds (lambda args...), named name if it comes from a defun.
+    pub fn from_list(
+        name: Option<Sym>,
+        args: &[Exp],
+        interpreter: &Interpreter,
+    ) -> Result<Lambda, LispErr> {
+        if let Some(Vector(lambda_list)) = args.first() {
+            let mut llist: Vec<Sym> = vec![];
+            let key_marker = Sym::intern("&key");
+            let mut keys = vec![];
+            let mut in_keys = false;
             for arg in lambda_list {
-                llist.push(match arg {
-                    Symbol(str) => str.clone(),
+                match arg {
+                    Symbol(sym) if *sym == key_marker => in_keys = true,
+                    Symbol(sym) if in_keys => keys.push((*sym, Keyword::intern(&sym.name()), None)),
+                    Symbol(sym) => llist.push(*sym),
+                    // (name default) is only allowed after &key
+                    Vector(pair) if in_keys => match pair.as_slice() {
+                        [Symbol(name), default] => {
+                            keys.push((*name, Keyword::intern(&name.name()), Some(default.clone())))
+                        }
+                        _ => return Err("Invalid &key parameter".into()),
+                    },
                     _ => return Err("Invalid lambda list".into()),
-                });
+                }
             }
 
-            let body = Vec::from(&args[1..]);
-            Ok(Lambda::new(llist, body))
+            Ok(Lambda::build(name, llist, keys, &args[1..], interpreter))
         } else {
-            return Err("Invalid lambda list".into());
+            Err("Invalid lambda list".into())
         }
     }
 
-    pub fn call(self: &Self, args: Vec<Exp>, env: &Arc<Env>) -> Result<Exp, LispErr> {
-        if self.args.len() != args.len() {
-            return Err("Wrong number of function arguments".into());
+    pub fn call(&self, args: Vec<Exp>, env: &Arc<Env>) -> Result<Exp, LispErr> {
+        let env = self.bind(args, env)?;
+        self.run(&env)
+    }
+
+    /// The frame a call with args from env runs in. Every call comes through
+    /// here, so it's where a cancelled evaluation stops.
+    pub(crate) fn bind(&self, args: Vec<Exp>, env: &Arc<Env>) -> Result<Arc<Env>, LispErr> {
+        cancel::check()?;
+        let def = &*self.def;
+        let name = || def.name.map_or("function".to_string(), |name| name.to_string());
+        if def.keys.is_empty() && args.len() != def.args.len() {
+            return Err(LispError::arity(name(), def.args.len(), args.len()));
+        }
+        if args.len() < def.args.len() {
+            let expected = format!("at least {}", def.args.len());
+            return Err(LispError::arity(name(), expected, args.len()));
         }
 
         let mut inner_env = Env::from_upper(env);
 
-        inner_env.local.extend(zip(self.args.clone(), args));
-        let inner_env = Arc::new(inner_env);
-        eval_many(&self.body, &inner_env)
+        let mut args = args.into_iter();
+        for (name, arg) in zip(def.args.iter().copied(), args.by_ref()) {
+            inner_env.insert(name, arg);
+        }
+        let mut inner_env = Arc::new(inner_env);
+        if !def.keys.is_empty() {
+            self.bind_keys(args.collect(), &mut inner_env)?;
+        }
+        Ok(inner_env)
+    }
+
+    pub(crate) fn run(&self, env: &Arc<Env>) -> Result<Exp, LispErr> {
+        eval_many(&self.def.body, env)
+    }
+
+    // Missing keys get their default evaluated in the frame as bound so far,
+    // or nil.
+    fn bind_keys(&self, rest: Vec<Exp>, inner_env: &mut Arc<Env>) -> Result<(), LispErr> {
+        if !rest.len().is_multiple_of(2) {
+            return Err("Odd number of keyword arguments".into());
+        }
+        let mut supplied = vec![];
+        for pair in rest.chunks(2) {
+            let Keyword(keyword) = &pair[0] else {
+                return Err(format!("Expected a keyword argument, found {}", pair[0]).into());
+            };
+            if !self.def.keys.iter().any(|(_, key, _)| key == keyword) {
+                return Err(format!("Unknown keyword argument {keyword}").into());
+            }
+            supplied.push((keyword, pair[1].clone()));
+        }
+        for (name, keyword, default) in &self.def.keys {
+            let value = match supplied.iter().rfind(|(key, _)| *key == keyword) {
+                Some((_, value)) => value.clone(),
+                None => match default {
+                    Some(default) => eval(default, inner_env)?,
+                    None => List(None),
+                },
+            };
+            // Only copies the frame if the default kept a reference to it.
+            Arc::make_mut(inner_env).insert(*name, value);
+        }
+        Ok(())
+    }
+}
+
+// Two lambdas are the same if they come from the same lambda form evaluation.
+impl PartialEq for Lambda {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.def, &other.def)
+    }
+}
+
+impl Eq for Lambda {}
+
+impl std::hash::Hash for Lambda {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.def).hash(state);
     }
 }
 
 impl fmt::Debug for Lambda {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Lambda with arguments: {:?}", self.args)
+        write!(f, "Lambda with arguments: {:?}", self.def.args)?;
+        if !self.def.keys.is_empty() {
+            let keys: Vec<&Sym> = self.def.keys.iter().map(|(name, _, _)| name).collect();
+            write!(f, " &key {keys:?}")?;
+        }
+        Ok(())
     }
 }
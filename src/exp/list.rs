@@ -1,5 +1,6 @@
 use crate::exp::Exp;
 use crate::exp::LispErr;
+use crate::limits;
 
 use std::fmt;
 use std::sync::Arc;
@@ -25,11 +26,13 @@ impl fmt::Display for Cons {
 
 impl Cons {
     pub fn new(car: Exp, cdr: Exp) -> Cons {
-        Cons { car: car, cdr: cdr }
+        limits::allocate(1);
+        Cons { car, cdr }
     }
 }
 
 pub fn push(val: Exp, list: List) -> List {
+    limits::allocate(1);
     Some(Arc::new(Cons {
         car: val,
         cdr: Exp::List(list),
@@ -37,13 +40,7 @@ pub fn push(val: Exp, list: List) -> List {
 }
 
 pub fn next(list: &List) -> List {
-    match list {
-        // The CDR of nil is nil
-        None => None,
-        Some(list) => {
-            return Some(list.clone());
-        }
-    }
+    list.as_ref().map(|list| list.clone())
 }
 
 pub fn list_from_slice(vec: &[Exp]) -> List {
@@ -72,15 +69,12 @@ pub fn dolist(
     list: &List,
     mut func: impl FnMut(&Exp) -> Result<(), LispErr>,
 ) -> Result<(), LispErr> {
-    match list {
-        Some(list) => {
-            func(&list.car)?;
-            match &list.cdr {
-                Exp::List(cdr) => dolist(&cdr, func)?,
-                _ => return Err("dolist argument is not a list".into()),
-            }
+    if let Some(list) = list {
+        func(&list.car)?;
+        match &list.cdr {
+            Exp::List(cdr) => dolist(cdr, func)?,
+            _ => return Err("dolist argument is not a list".into()),
         }
-        None => {}
     }
     Ok(())
 }
@@ -0,0 +1,317 @@
+use crate::exp::Exp;
+
+use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+
+// Persistent hash array mapped trie. Every update copies only the path from
+// the root to the changed leaf, everything else is shared between versions.
+
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+enum Node {
+    // All the entries whose keys share the same full hash.
+    Leaf(u64, Vec<(Exp, Exp)>),
+    Branch(u32, Vec<Arc<Node>>),
+}
+
+fn hash_of(key: &Exp) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn slot(hash: u64, shift: u32) -> u32 {
+    1 << ((hash >> shift) & MASK)
+}
+
+fn position(bitmap: u32, bit: u32) -> usize {
+    (bitmap & (bit - 1)).count_ones() as usize
+}
+
+impl Node {
+    fn get(&self, hash: u64, shift: u32, key: &Exp) -> Option<&Exp> {
+        match self {
+            Node::Leaf(h, entries) => {
+                if *h != hash {
+                    return None;
+                }
+                entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            Node::Branch(bitmap, children) => {
+                let bit = slot(hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                children[position(*bitmap, bit)].get(hash, shift + BITS, key)
+            }
+        }
+    }
+
+    // Returns the new node and whether the key was not present before.
+    fn insert(&self, hash: u64, shift: u32, key: Exp, val: Exp) -> (Node, bool) {
+        match self {
+            Node::Leaf(h, entries) if *h == hash => {
+                let mut entries = entries.clone();
+                match entries.iter_mut().find(|(k, _)| *k == key) {
+                    Some(entry) => {
+                        entry.1 = val;
+                        (Node::Leaf(hash, entries), false)
+                    }
+                    None => {
+                        entries.push((key, val));
+                        (Node::Leaf(hash, entries), true)
+                    }
+                }
+            }
+            Node::Leaf(h, entries) => {
+                // Two different hashes ended up in the same slot, push the
+                // existing leaf one level down and retry.
+                let existing = Node::Leaf(*h, entries.clone());
+                let branch = Node::Branch(slot(*h, shift), vec![Arc::new(existing)]);
+                branch.insert(hash, shift, key, val)
+            }
+            Node::Branch(bitmap, children) => {
+                let bit = slot(hash, shift);
+                let pos = position(*bitmap, bit);
+                let mut children = children.clone();
+                if bitmap & bit == 0 {
+                    children.insert(pos, Arc::new(Node::Leaf(hash, vec![(key, val)])));
+                    (Node::Branch(bitmap | bit, children), true)
+                } else {
+                    let (child, added) = children[pos].insert(hash, shift + BITS, key, val);
+                    children[pos] = Arc::new(child);
+                    (Node::Branch(*bitmap, children), added)
+                }
+            }
+        }
+    }
+
+    // None if the key was not found, Some(None) if the node became empty.
+    fn remove(&self, hash: u64, shift: u32, key: &Exp) -> Option<Option<Arc<Node>>> {
+        match self {
+            Node::Leaf(h, entries) => {
+                if *h != hash {
+                    return None;
+                }
+                let index = entries.iter().position(|(k, _)| k == key)?;
+                if entries.len() == 1 {
+                    return Some(None);
+                }
+                let mut entries = entries.clone();
+                entries.remove(index);
+                Some(Some(Arc::new(Node::Leaf(hash, entries))))
+            }
+            Node::Branch(bitmap, children) => {
+                let bit = slot(hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                let pos = position(*bitmap, bit);
+                let mut children = children.clone();
+                let bitmap = match children[pos].remove(hash, shift + BITS, key)? {
+                    Some(child) => {
+                        children[pos] = child;
+                        *bitmap
+                    }
+                    None => {
+                        children.remove(pos);
+                        bitmap & !bit
+                    }
+                };
+                match children.as_slice() {
+                    [] => Some(None),
+                    // A lone leaf doesn't need a branch above it.
+                    [only] if matches!(**only, Node::Leaf(..)) => Some(Some(only.clone())),
+                    _ => Some(Some(Arc::new(Node::Branch(bitmap, children)))),
+                }
+            }
+        }
+    }
+}
+
+/// Immutable hash map from Exp to Exp with structural sharing.
+#[derive(Clone, Default)]
+pub struct Map {
+    root: Option<Arc<Node>>,
+    len: usize,
+}
+
+impl Map {
+    pub fn new() -> Map {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &Exp) -> Option<&Exp> {
+        self.root.as_ref()?.get(hash_of(key), 0, key)
+    }
+
+    pub fn contains_key(&self, key: &Exp) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&self, key: Exp, val: Exp) -> Map {
+        let hash = hash_of(&key);
+        let (root, added) = match &self.root {
+            Some(root) => root.insert(hash, 0, key, val),
+            None => (Node::Leaf(hash, vec![(key, val)]), true),
+        };
+        Map {
+            root: Some(Arc::new(root)),
+            len: self.len + added as usize,
+        }
+    }
+
+    pub fn remove(&self, key: &Exp) -> Map {
+        let Some(root) = &self.root else {
+            return self.clone();
+        };
+        match root.remove(hash_of(key), 0, key) {
+            None => self.clone(),
+            Some(root) => Map {
+                root,
+                len: self.len - 1,
+            },
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            stack: self.root.iter().map(|root| (&**root, 0)).collect(),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Exp> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Exp> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+pub struct Iter<'a> {
+    stack: Vec<(&'a Node, usize)>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Exp, &'a Exp);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, index) = self.stack.last_mut()?;
+            let node: &'a Node = node;
+            match node {
+                Node::Leaf(_, entries) if *index < entries.len() => {
+                    *index += 1;
+                    let (k, v) = &entries[*index - 1];
+                    return Some((k, v));
+                }
+                Node::Branch(_, children) if *index < children.len() => {
+                    *index += 1;
+                    let child = &*children[*index - 1];
+                    self.stack.push((child, 0));
+                }
+                _ => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl FromIterator<(Exp, Exp)> for Map {
+    fn from_iter<T: IntoIterator<Item = (Exp, Exp)>>(iter: T) -> Self {
+        iter.into_iter()
+            .fold(Map::new(), |map, (k, v)| map.insert(k, v))
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl Eq for Map {}
+
+impl Hash for Map {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Order independent, equal maps may have been built in different orders.
+        let sum = self.iter().fold(0u64, |acc, (k, v)| {
+            let mut hasher = DefaultHasher::new();
+            k.hash(&mut hasher);
+            v.hash(&mut hasher);
+            acc.wrapping_add(hasher.finish())
+        });
+        self.len.hash(state);
+        sum.hash(state);
+    }
+}
+
+impl fmt::Debug for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Immutable hash set of Exp, a Map whose values are ignored.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Set {
+    map: Map,
+}
+
+impl Set {
+    pub fn new() -> Set {
+        Set::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn contains(&self, key: &Exp) -> bool {
+        self.map.contains_key(key)
+    }
+
+    pub fn insert(&self, key: Exp) -> Set {
+        Set {
+            map: self.map.insert(key, Exp::Bool(true)),
+        }
+    }
+
+    pub fn remove(&self, key: &Exp) -> Set {
+        Set {
+            map: self.map.remove(key),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Exp> {
+        self.map.keys()
+    }
+}
+
+impl FromIterator<Exp> for Set {
+    fn from_iter<T: IntoIterator<Item = Exp>>(iter: T) -> Self {
+        iter.into_iter().fold(Set::new(), |set, k| set.insert(k))
+    }
+}
+
+impl fmt::Debug for Set {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
@@ -1,30 +1,155 @@
+pub mod character;
+pub mod form;
+pub mod keyword;
 pub mod lambda;
 pub mod list;
+pub mod map;
+pub mod native;
+pub mod object;
+pub mod symbol;
+pub mod var;
 
+pub use form::Form;
+pub use keyword::Keyword;
 pub use lambda::Lambda;
 pub use list::Cons;
 pub use list::List;
+pub use map::{Map, Set};
+pub use native::Native;
+pub use object::Object;
+pub use symbol::Sym;
+pub use var::VarRef;
 
 use list::dolist;
 
-use std::{error::Error, fmt, sync::Arc};
+use std::hash::{Hash, Hasher};
+use std::{fmt, sync::Arc};
 
-pub type LispErr = Box<dyn Error>;
+pub type LispErr = crate::error::LispError;
 pub type NativeFunction = fn(&[Exp], &Arc<Env>) -> Result<Exp, LispErr>;
 pub type Macro = fn(&[Exp], &Arc<Env>) -> Result<Vec<Exp>, LispErr>;
+/// Gets its arguments unevaluated like a macro, but returns the value of the
+/// form rather than code to evaluate.
+pub type SpecialForm = fn(&[Exp], &Arc<Env>) -> Result<Exp, LispErr>;
 
 
 #[derive(Clone, Debug)]
 pub enum Exp {
     List(Option<Arc<Cons>>),
     Num(i64),
-    Symbol(String),
+    Symbol(Sym),
     Str(String),
-    Vector(Vec<Exp>),
+    Vector(Form),
     Lambda(Lambda), 
     Func(NativeFunction),
     Macro(Macro),
+    Special(SpecialForm),
     Bool(bool),
+    Char(char),
+    Map(Map),
+    Set(Set),
+    Keyword(Keyword),
+    Native(Native),
+    Object(Object),
+    // Variable references produced by the resolver, never by the reader.
+    Var(VarRef),
+}
+
+// Structural equality, used for map keys and equal?. Functions and macros are
+// only equal to themselves.
+impl PartialEq for Exp {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (List(a), List(b)) => match (a, b) {
+                (None, None) => true,
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b) || (a.car == b.car && a.cdr == b.cdr),
+                _ => false,
+            },
+            (Num(a), Num(b)) => a == b,
+            (Symbol(a), Symbol(b)) => a == b,
+            (Str(a), Str(b)) => a == b,
+            (Vector(a), Vector(b)) => a == b,
+            (Lambda(a), Lambda(b)) => a == b,
+            (Func(a), Func(b)) => std::ptr::fn_addr_eq(*a, *b),
+            (Macro(a), Macro(b)) => std::ptr::fn_addr_eq(*a, *b),
+            (Special(a), Special(b)) => std::ptr::fn_addr_eq(*a, *b),
+            (Bool(a), Bool(b)) => a == b,
+            (Char(a), Char(b)) => a == b,
+            (Map(a), Map(b)) => a == b,
+            (Set(a), Set(b)) => a == b,
+            (Keyword(a), Keyword(b)) => a == b,
+            (Native(a), Native(b)) => a == b,
+            (Object(a), Object(b)) => a == b,
+            (Var(a), Var(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Exp {}
+
+impl Hash for Exp {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            List(None) => {}
+            List(Some(cons)) => {
+                cons.car.hash(state);
+                cons.cdr.hash(state);
+            }
+            Num(n) => n.hash(state),
+            Symbol(s) => s.hash(state),
+            Str(s) => s.hash(state),
+            Vector(v) => v.hash(state),
+            Lambda(lambda) => lambda.hash(state),
+            Func(f) => (*f as usize).hash(state),
+            Macro(m) => (*m as usize).hash(state),
+            Special(s) => (*s as usize).hash(state),
+            Bool(b) => b.hash(state),
+            Char(c) => c.hash(state),
+            Map(map) => map.hash(state),
+            Set(set) => set.hash(state),
+            Keyword(k) => k.hash(state),
+            Native(native) => native.hash(state),
+            Object(object) => object.hash(state),
+            Var(var) => var.hash(state),
+        }
+    }
+}
+
+impl Exp {
+    /// Name of the kind of value, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            List(None) => "nil",
+            List(Some(_)) => "list",
+            Num(_) => "integer",
+            Symbol(_) => "symbol",
+            Str(_) => "string",
+            Vector(_) => "vector",
+            Lambda(_) | Func(_) | Native(_) => "function",
+            Macro(_) | Special(_) => "macro",
+            Bool(_) => "boolean",
+            Char(_) => "character",
+            Map(_) => "map",
+            Set(_) => "set",
+            Keyword(_) => "keyword",
+            Object(object) => object.type_name(),
+            Var(_) => "variable",
+        }
+    }
+
+    /// The expression written back as it would appear in source code.
+    pub fn to_source(&self) -> String {
+        match self {
+            Vector(form) => {
+                let items: Vec<String> = form.iter().map(Exp::to_source).collect();
+                format!("({})", items.join(" "))
+            }
+            Str(s) => format!("{s:?}"),
+            other => other.to_string(),
+        }
+    }
 }
 
 pub fn to_bool(exp: &Exp) -> bool {
@@ -38,25 +163,40 @@ pub use Exp::*;
 
 use crate::env::Env;
 
+// Prints strings quoted, so maps and sets show {"ada" 36} the way it was
+// written. Characters already print as #\a.
+struct Readable<'a>(&'a Exp);
+
+impl fmt::Display for Readable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Str(s) => write!(f, "{s:?}"),
+            other => write!(f, "{other}"),
+        }
+    }
+}
+
 impl fmt::Display for Exp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             List(list) => {
                 write!(f, "(").unwrap();
-                match dolist(list, |exp| {
+                if dolist(list, |exp| {
                     write!(f, "{exp} ").unwrap();
                     Ok(())
-                }) {
-                    Err(_) => {
-                        write!(f, "PRINTING CONS IS UNIMPLEMENTED").unwrap();
-                    }
-                    _ => {}
+                })
+                .is_err()
+                {
+                    write!(f, "PRINTING CONS IS UNIMPLEMENTED").unwrap();
                 };
                 write!(f, ")")
             }
             Num(n) => write!(f, "{n}"),
             Symbol(s) => write!(f, "{s}"),
             Str(s) => write!(f, "{s}"),
+            Char(c) => write!(f, "#\\{}", character::char_name(*c)),
+            Keyword(k) => write!(f, "{k}"),
+            Var(var) => write!(f, "{}", var.name()),
             Vector(v) => {
                 write!(f, "[").unwrap();
                 for element in v.iter() {
@@ -64,7 +204,25 @@ impl fmt::Display for Exp {
                 }
                 write!(f, "]")
             }
+            Map(map) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in map.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { " " };
+                    write!(f, "{sep}{} {}", Readable(k), Readable(v))?;
+                }
+                write!(f, "}}")
+            }
+            Set(set) => {
+                write!(f, "#{{")?;
+                for (i, k) in set.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { " " };
+                    write!(f, "{sep}{}", Readable(k))?;
+                }
+                write!(f, "}}")
+            }
             Lambda(lambda) => write!(f, "{lambda:?}"),
+            Native(native) => write!(f, "{native:?}"),
+            Object(object) => write!(f, "{object:?}"),
             other => write!(f, "{other:?}"),
         }
     }
@@ -0,0 +1,55 @@
+use crate::env::Env;
+use crate::exp::{Exp, LispErr};
+
+use std::fmt;
+use std::sync::Arc;
+
+pub type NativeClosure = dyn Fn(&[Exp], &Arc<Env>) -> Result<Exp, LispErr> + Send + Sync;
+
+/// A function implemented by the host application. Unlike Func it can capture
+/// state, so it's kept behind an Arc and compared by identity.
+#[derive(Clone)]
+pub struct Native {
+    name: Arc<str>,
+    func: Arc<NativeClosure>,
+}
+
+impl Native {
+    pub fn new(
+        name: &str,
+        func: impl Fn(&[Exp], &Arc<Env>) -> Result<Exp, LispErr> + Send + Sync + 'static,
+    ) -> Native {
+        Native {
+            name: Arc::from(name),
+            func: Arc::new(func),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn call(&self, args: &[Exp], env: &Arc<Env>) -> Result<Exp, LispErr> {
+        (self.func)(args, env)
+    }
+}
+
+impl PartialEq for Native {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.func, &other.func)
+    }
+}
+
+impl Eq for Native {}
+
+impl std::hash::Hash for Native {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.func) as *const () as usize).hash(state)
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#<native {}>", self.name)
+    }
+}
@@ -0,0 +1,94 @@
+use std::any::{Any, TypeId};
+use std::fmt;
+use std::sync::Arc;
+
+/// A Rust value handed to Lisp code. Scripts can only pass it around, compare
+/// it by identity and call the methods the host registered for its type.
+#[derive(Clone)]
+pub struct Object {
+    type_name: &'static str,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+/// Last path segment of T's name, "Connection" for "my_crate::db::Connection".
+pub fn short_type_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let base = name.split('<').next().unwrap_or(name);
+    let start = base.rfind("::").map(|i| i + 2).unwrap_or(0);
+    &name[start..]
+}
+
+impl Object {
+    pub fn new<T: Any + Send + Sync>(value: T) -> Object {
+        Object::from_arc(Arc::new(value))
+    }
+
+    pub fn from_arc<T: Any + Send + Sync>(value: Arc<T>) -> Object {
+        Object {
+            type_name: short_type_name::<T>(),
+            value,
+        }
+    }
+
+    /// Like new, with the name used when printing the object.
+    pub fn with_type_name<T: Any + Send + Sync>(type_name: &'static str, value: T) -> Object {
+        Object {
+            type_name,
+            value: Arc::new(value),
+        }
+    }
+
+    /// Like from_arc, with the name used when printing the object.
+    pub fn from_arc_with_type_name<T: Any + Send + Sync>(
+        type_name: &'static str,
+        value: Arc<T>,
+    ) -> Object {
+        Object { type_name, value }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// TypeId of the wrapped value, not of the Arc around it.
+    pub fn value_type_id(&self) -> TypeId {
+        (*self.value).type_id()
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.value.is::<T>()
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref::<T>()
+    }
+
+    pub fn downcast<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.value.clone().downcast::<T>().ok()
+    }
+}
+
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.value, &other.value)
+    }
+}
+
+impl Eq for Object {}
+
+impl std::hash::Hash for Object {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.value) as *const () as usize).hash(state)
+    }
+}
+
+impl fmt::Debug for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#<{} {:p}>",
+            self.type_name,
+            Arc::as_ptr(&self.value) as *const ()
+        )
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+
+use lazy_static::lazy_static;
+
+struct SymbolTable {
+    ids: HashMap<Arc<str>, Sym>,
+    names: Vec<Arc<str>>,
+}
+
+lazy_static! {
+    static ref SYMBOLS: RwLock<SymbolTable> = RwLock::new(SymbolTable {
+        ids: HashMap::new(),
+        names: vec![],
+    });
+}
+
+// Which symbols some frame has bound, by id, in chunks allocated on first
+// use so reading never takes a lock. Ids past the last chunk count as bound.
+const CHUNK: usize = 4096;
+static BOUND_LOCALLY: [OnceLock<Box<[AtomicBool]>>; 1024] = [const { OnceLock::new() }; 1024];
+
+/// Interned symbol. Two symbols with the same name read from source are the
+/// same id, so comparing and hashing them is as cheap as for an integer.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Sym(u32);
+
+impl Sym {
+    pub fn intern(name: &str) -> Sym {
+        if let Some(sym) = SYMBOLS
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .ids
+            .get(name)
+        {
+            return *sym;
+        }
+        let mut table = SYMBOLS.write().unwrap_or_else(|e| e.into_inner());
+        // Someone else may have interned it between the two locks.
+        if let Some(sym) = table.ids.get(name) {
+            return *sym;
+        }
+        let sym = Sym(table.names.len() as u32);
+        let name: Arc<str> = Arc::from(name);
+        table.names.push(name.clone());
+        table.ids.insert(name, sym);
+        sym
+    }
+
+    /// A symbol that is different from every other symbol, including the ones
+    /// that print the same, because it never enters the name lookup table.
+    pub fn gensym(prefix: &str) -> Sym {
+        let mut table = SYMBOLS.write().unwrap_or_else(|e| e.into_inner());
+        let sym = Sym(table.names.len() as u32);
+        table.names.push(Arc::from(format!("{prefix}{}", sym.0)));
+        sym
+    }
+
+    /// Notes that a frame binds the symbol, so free references to it have
+    /// to look through the frames from now on.
+    pub(crate) fn mark_bound_locally(self) {
+        let id = self.0 as usize;
+        if let Some(chunk) = BOUND_LOCALLY.get(id / CHUNK) {
+            let chunk = chunk.get_or_init(|| (0..CHUNK).map(|_| AtomicBool::new(false)).collect());
+            if !chunk[id % CHUNK].load(Ordering::Relaxed) {
+                chunk[id % CHUNK].store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// False if no frame has ever bound the symbol.
+    pub(crate) fn may_be_bound_locally(self) -> bool {
+        let id = self.0 as usize;
+        match BOUND_LOCALLY.get(id / CHUNK) {
+            Some(chunk) => chunk
+                .get()
+                .is_some_and(|chunk| chunk[id % CHUNK].load(Ordering::Relaxed)),
+            None => true,
+        }
+    }
+
+    pub fn name(&self) -> Arc<str> {
+        SYMBOLS.read().unwrap_or_else(|e| e.into_inner()).names[self.0 as usize].clone()
+    }
+}
+
+impl fmt::Debug for Sym {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for Sym {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Symbol ids are small and unique, they don't need SipHash.
+#[derive(Default)]
+pub struct SymHasher(u64);
+
+impl Hasher for SymHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 << 8 | *byte as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.0 = (n as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    }
+}
+
+pub type SymMap<V> = HashMap<Sym, V, BuildHasherDefault<SymHasher>>;
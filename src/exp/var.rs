@@ -0,0 +1,45 @@
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use crate::env::Global;
+use crate::exp::Sym;
+
+/// A variable reference made by the resolver when a lambda is created. Only
+/// the interpreter makes and reads them.
+#[derive(Clone, Debug)]
+pub struct VarRef(pub(crate) Address);
+
+#[derive(Clone, Debug)]
+pub(crate) enum Address {
+    /// Frame depth and index in the frame.
+    Local(u32, u32, Sym),
+    Global(Arc<Global>),
+}
+
+impl VarRef {
+    pub fn name(&self) -> Sym {
+        match &self.0 {
+            Address::Local(_, _, sym) => *sym,
+            Address::Global(cell) => cell.name(),
+        }
+    }
+}
+
+impl PartialEq for VarRef {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Address::Local(d1, i1, a), Address::Local(d2, i2, b)) => (d1, i1, a) == (d2, i2, b),
+            (Address::Global(a), Address::Global(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Hash for VarRef {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.0 {
+            Address::Local(depth, index, sym) => (depth, index, sym).hash(state),
+            Address::Global(cell) => cell.name().hash(state),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::cancel::{self, CancelToken};
+use crate::error::LispError;
+use crate::exp::*;
+use crate::limits;
+use crate::pool;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// A value that is set once and waited for by anyone who needs it. Futures
+// are promises that a pool job delivers.
+
+/// A result that isn't there yet.
+#[derive(Default)]
+pub struct Promise {
+    result: OnceLock<Result<Exp, LispErr>>,
+    lock: Mutex<()>,
+    delivered: Condvar,
+}
+
+impl Promise {
+    pub fn new() -> Arc<Promise> {
+        Arc::new(Promise::default())
+    }
+
+    /// The promise as a Lisp value.
+    pub fn to_exp(self: &Arc<Self>, type_name: &'static str) -> Exp {
+        Object(Object::from_arc_with_type_name(type_name, self.clone()))
+    }
+
+    /// Sets the result, unless it was set already. Returns whether it was.
+    pub fn deliver(&self, result: Result<Exp, LispErr>) -> bool {
+        let delivered = self.result.set(result).is_ok();
+        if delivered {
+            let _lock = self.lock.lock().unwrap();
+            self.delivered.notify_all();
+        }
+        delivered
+    }
+
+    pub fn is_realized(&self) -> bool {
+        self.result.get().is_some()
+    }
+
+    /// Waits for the result until deadline, or forever without one. Errors
+    /// are raised again in the waiting thread, which gets a cancelled error
+    /// if it's cancelled meanwhile.
+    pub fn wait(&self, deadline: Option<Instant>) -> Option<Result<Exp, LispErr>> {
+        loop {
+            if let Some(result) = self.result.get() {
+                return Some(result.clone().map_err(LispError::reraise));
+            }
+            // A worker keeps the pool going while it waits, the job it
+            // waits for may be queued behind it.
+            if pool::run_pending() {
+                continue;
+            }
+            let lock = self.lock.lock().unwrap();
+            if self.is_realized() {
+                continue;
+            }
+            if let Err(err) = cancel::check() {
+                return Some(Err(err));
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return None;
+            }
+            let mut timeout = cancel::wait_slice(deadline);
+            // Workers look for new jobs every now and then.
+            if pool::is_worker() {
+                let poll = Duration::from_millis(1);
+                timeout = Some(timeout.map_or(poll, |timeout| timeout.min(poll)));
+            }
+            match timeout {
+                Some(timeout) => drop(self.delivered.wait_timeout(lock, timeout).unwrap()),
+                None => drop(self.delivered.wait(lock).unwrap()),
+            }
+        }
+    }
+}
+
+/// Runs f on the pool, delivering its result to the promise returned. f is
+/// cancelled along with the code that started it.
+pub fn future(f: impl FnOnce() -> Result<Exp, LispErr> + Send + 'static) -> Arc<Promise> {
+    let promise = Promise::new();
+    let delivered = promise.clone();
+    let token = CancelToken::spawned();
+    let meter = limits::current();
+    pool::spawn(move || {
+        let result = catch_unwind(AssertUnwindSafe(|| limits::metered(meter, || token.run(f))))
+            .unwrap_or_else(|payload| Err(LispError::panic(&"future", payload)));
+        delivered.deliver(result);
+    });
+    promise
+}
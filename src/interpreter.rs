@@ -0,0 +1,393 @@
+use crate::condition::{Debugger, RestartInfo};
+use crate::convert::IntoNative;
+use crate::env::{init_toplevel, Env, Global};
+use crate::error::LispError;
+use crate::eval::eval;
+use crate::exp::object::short_type_name;
+use crate::exp::symbol::SymMap;
+use crate::exp::*;
+use crate::limits::{self, Limits, Meter};
+use crate::parser::parse;
+use crate::thread::Thread;
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use arc_swap::{ArcSwap, ArcSwapOption};
+use lazy_static::lazy_static;
+
+lazy_static! {
+    // Built once, every interpreter starts from a copy.
+    static ref BUILTINS: Vec<(Sym, Exp)> = init_toplevel()
+        .into_iter()
+        .map(|(name, val)| (Sym::intern(&name), val))
+        .collect();
+}
+
+/// The name a builtin function is defined under, for error messages about
+/// calls that only have the function value.
+pub(crate) fn builtin_name(func: NativeFunction) -> Option<Sym> {
+    BUILTINS.iter().find_map(|(name, value)| match value {
+        Func(builtin) if std::ptr::fn_addr_eq(*builtin, func) => Some(*name),
+        _ => None,
+    })
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Builtins that don't touch anything outside the code running them: the
+/// special forms, arithmetic, lists, maps and characters. A starting point
+/// for the allow-list of a sandbox.
+#[rustfmt::skip]
+pub const PURE_BUILTINS: &[&str] = &[
+    "def", "defun", "lambda", "let", "if", "or", "progn", "dotimes", "try", "error", "raise",
+    "assert", "condition-kind", "+", "-", "*", "=", "equal?", "cons", "car", "cdr", "list",
+    "count", "gensym", "hash-map", "hash-set", "get", "assoc", "dissoc", "conj", "disj",
+    "contains?", "keys", "vals", "merge", "update", "keyword", "keyword?", "type-of",
+    "char->integer", "integer->char", "string-ref", "string->list", "list->string",
+    "char-alphabetic?", "char-numeric?", "char-whitespace?", "char-upper-case?",
+    "char-lower-case?",
+];
+
+struct Globals {
+    id: u64,
+    // Only ever grows. Adding a name copies the table, so looking one up
+    // never waits for a definition.
+    table: ArcSwap<SymMap<Arc<Global>>>,
+    // Methods of host object types, called with invoke.
+    methods: RwLock<HashMap<(TypeId, Sym), Native>>,
+    debugger: RwLock<Option<Debugger>>,
+    // Threads spawned from Lisp that nobody has joined yet.
+    threads: Mutex<Vec<Thread>>,
+    // What evaluations may still use, when there are limits.
+    meter: ArcSwapOption<Meter>,
+}
+
+/// An independent set of global definitions. Cloning gives another handle to
+/// the same globals, fork gives a copy that evolves separately.
+#[derive(Clone)]
+pub struct Interpreter {
+    globals: Arc<Globals>,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter::with_globals(BUILTINS.iter().cloned(), vec![], HashMap::new())
+    }
+
+    fn with_globals(
+        globals: impl Iterator<Item = (Sym, Exp)>,
+        denied: Vec<Sym>,
+        methods: HashMap<(TypeId, Sym), Native>,
+    ) -> Interpreter {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let mut table: SymMap<Arc<Global>> = globals
+            .map(|(name, val)| (name, Arc::new(Global::new(name, id, Some(val)))))
+            .collect();
+        // Denied names keep whatever the code defined under them itself.
+        for name in denied {
+            let value = table.get(&name).and_then(|cell| cell.peek());
+            table.insert(name, Arc::new(Global::denied(name, id, value)));
+        }
+        Interpreter {
+            globals: Arc::new(Globals {
+                id,
+                table: ArcSwap::from_pointee(table),
+                methods: RwLock::new(methods),
+                debugger: RwLock::new(None),
+                threads: Mutex::new(vec![]),
+                meter: ArcSwapOption::empty(),
+            }),
+        }
+    }
+
+    /// A new interpreter starting with the current globals of this one.
+    /// Definitions made afterwards in either of them aren't seen by the other.
+    pub fn fork(&self) -> Interpreter {
+        let table = self.globals.table.load();
+        let globals: Vec<(Sym, Exp)> = table
+            .iter()
+            .filter_map(|(name, cell)| Some((*name, cell.peek()?)))
+            .collect();
+        let denied = table
+            .iter()
+            .filter(|(_, cell)| cell.is_denied())
+            .map(|(name, _)| *name)
+            .collect();
+        let methods = self.globals.methods.read().unwrap().clone();
+        let fork = Interpreter::with_globals(globals.into_iter(), denied, methods);
+        for (name, _) in table.iter().filter(|(_, cell)| cell.is_dynamic()) {
+            fork.global_cell(*name).make_dynamic();
+        }
+        *fork.globals.debugger.write().unwrap() = self.debugger();
+        fork
+    }
+
+    /// A new interpreter with only the builtin functions named in allowed,
+    /// of those this one has, so a sandbox can't give more than it was
+    /// given. The rest fail with a permission error, except for plain builtin
+    /// values such as nil and true. Host functions and methods named in
+    /// allowed are kept as well, and evaluations count against the limits of
+    /// this interpreter.
+    ///
+    /// ```
+    /// use lisp::PURE_BUILTINS;
+    ///
+    /// let sandbox = lisp::Interpreter::new().sandbox(PURE_BUILTINS);
+    /// assert_eq!(sandbox.eval_str("(car (list 1 2))").unwrap(), lisp::Exp::Num(1));
+    /// let err = sandbox.eval_str("(print 1)").unwrap_err();
+    /// assert_eq!(err.to_string(), "Permission denied: print");
+    /// ```
+    pub fn sandbox(&self, allowed: &[&str]) -> Interpreter {
+        let allowed: Vec<Sym> = allowed.iter().map(|name| Sym::intern(name)).collect();
+        // Builtin values, not whatever this interpreter has under their names.
+        let builtin = |name: &Sym| {
+            BUILTINS
+                .iter()
+                .find(|(builtin, _)| builtin == name)
+                .map(|(_, value)| value.clone())
+        };
+        let is_function =
+            |value: &Exp| matches!(value, Func(_) | Macro(_) | Special(_) | Native(_));
+        let mut globals = vec![];
+        let mut denied = vec![];
+        for (name, cell) in self.globals.table.load().iter() {
+            match (cell.peek(), builtin(name)) {
+                (Some(value @ Native(_)), _) if allowed.contains(name) && !cell.is_denied() => {
+                    globals.push((*name, value))
+                }
+                (Some(_), Some(value)) if allowed.contains(name) && !cell.is_denied() => {
+                    globals.push((*name, value))
+                }
+                (_, Some(value)) if !is_function(&value) => globals.push((*name, value)),
+                (Some(_), _) => denied.push(*name),
+                (None, _) if cell.is_denied() => denied.push(*name),
+                (None, _) => {}
+            }
+        }
+        let methods = self
+            .globals
+            .methods
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|((_, method), _)| allowed.contains(method))
+            .map(|(key, method)| (*key, method.clone()))
+            .collect();
+        let sandbox = Interpreter::with_globals(globals.into_iter(), denied, methods);
+        sandbox.globals.meter.store(self.globals.meter.load_full());
+        sandbox
+    }
+
+    pub fn id(&self) -> u64 {
+        self.globals.id
+    }
+
+    /// A fresh toplevel environment for evaluating code in this interpreter.
+    pub fn env(&self) -> Arc<Env> {
+        Arc::new(Env::new(self))
+    }
+
+    pub fn eval(&self, exp: &Exp) -> Result<Exp, LispErr> {
+        self.metered(|| eval(exp, &self.env()))
+    }
+
+    /// Reads and evaluates every form in src, returning the value of the last
+    /// one, or nil if there are none.
+    ///
+    /// ```
+    /// let interpreter = lisp::Interpreter::new();
+    /// interpreter.eval_str("(defun square (x) (* x x))").unwrap();
+    /// let res = interpreter.eval_str("(square 7)").unwrap();
+    /// assert_eq!(res, lisp::Exp::Num(49));
+    /// ```
+    pub fn eval_str(&self, src: &str) -> Result<Exp, LispErr> {
+        let env = self.env();
+        let mut res = List(None);
+        for form in parse(src)? {
+            res = self.metered(|| eval(&form, &env))?;
+        }
+        Ok(res)
+    }
+
+    /// Limits what evaluations started from now on may use, all of them
+    /// together. The usage counts start from zero again.
+    pub fn set_limits(&self, limits: Limits) {
+        self.globals.meter.store(Some(Meter::new(limits)));
+    }
+
+    fn metered<T>(&self, f: impl FnOnce() -> T) -> T {
+        match self.globals.meter.load_full() {
+            Some(meter) => limits::metered(Some(meter), f),
+            None => f(),
+        }
+    }
+
+    pub fn get_global(&self, symbol: Sym) -> Result<Exp, LispErr> {
+        match self.globals.table.load().get(&symbol) {
+            Some(cell) => cell.get(),
+            None => Err(LispError::unbound(symbol)),
+        }
+    }
+
+    /// Reads a resolved global reference. The cell may come from code that was
+    /// resolved by another interpreter, e.g. a lambda defined before a fork.
+    pub(crate) fn read_global(&self, cell: &Global) -> Result<Exp, LispErr> {
+        if cell.owner() == self.globals.id {
+            cell.get()
+        } else {
+            self.get_global(cell.name())
+        }
+    }
+
+    pub fn set_global(&self, symbol: Sym, val: Exp) {
+        self.global_cell(symbol).set(val);
+    }
+
+    /// Defines name as a Lisp function that calls the Rust closure f, with the
+    /// arguments and the result converted through FromExp and IntoExp.
+    ///
+    /// ```
+    /// let interpreter = lisp::Interpreter::new();
+    /// interpreter.register_fn("add", |a: i64, b: i64| a + b);
+    /// assert_eq!(interpreter.eval_str("(add 1 2)").unwrap(), lisp::Exp::Num(3));
+    /// ```
+    pub fn register_fn<Args>(&self, name: &str, f: impl IntoNative<Args>) {
+        self.set_global(Sym::intern(name), Native(f.into_native(name)));
+    }
+
+    /// Adds a method to host objects of type T, callable from Lisp as
+    /// (invoke object :name args...). f gets the object as its first argument.
+    ///
+    /// ```
+    /// use lisp::exp::Object;
+    /// use std::sync::Arc;
+    ///
+    /// struct File {
+    ///     size: i64,
+    /// }
+    ///
+    /// let interpreter = lisp::Interpreter::new();
+    /// interpreter.register_fn("open", |size: i64| Object::new(File { size }));
+    /// interpreter.register_method::<File, _>("size", |file: Arc<File>| file.size);
+    /// let res = interpreter.eval_str("(invoke (open 10) :size)").unwrap();
+    /// assert_eq!(res, lisp::Exp::Num(10));
+    /// ```
+    pub fn register_method<T: Any, Args>(&self, name: &str, f: impl IntoNative<Args>) {
+        let method = f.into_native(&format!("{}.{name}", short_type_name::<T>()));
+        let mut methods = self.globals.methods.write().unwrap();
+        methods.insert((TypeId::of::<T>(), Sym::intern(name)), method);
+    }
+
+    pub fn find_method(&self, object: &Object, name: Sym) -> Option<Native> {
+        let methods = self.globals.methods.read().unwrap();
+        methods.get(&(object.value_type_id(), name)).cloned()
+    }
+
+    /// Names of the methods registered for the type of object, sorted.
+    pub fn method_names(&self, object: &Object) -> Vec<Sym> {
+        let methods = self.globals.methods.read().unwrap();
+        let type_id = object.value_type_id();
+        let mut names: Vec<Sym> = methods
+            .keys()
+            .filter(|(id, _)| *id == type_id)
+            .map(|(_, name)| *name)
+            .collect();
+        names.sort_by_key(|name| name.name());
+        names
+    }
+
+    /// Like register_fn for closures that want the evaluated arguments as they
+    /// are, e.g. to take any number of them.
+    pub fn register_native(
+        &self,
+        name: &str,
+        f: impl Fn(&[Exp], &Arc<Env>) -> Result<Exp, LispErr> + Send + Sync + 'static,
+    ) {
+        self.set_global(Sym::intern(name), Native(Native::new(name, f)));
+    }
+
+    /// Lets f choose a restart for errors that no handler takes, e.g. by
+    /// asking the user in a REPL.
+    pub fn set_debugger(
+        &self,
+        f: impl Fn(&Interpreter, &LispError, &[RestartInfo]) -> Option<(usize, Vec<Exp>)>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        *self.globals.debugger.write().unwrap() = Some(Arc::new(f));
+    }
+
+    pub fn debugger(&self) -> Option<Debugger> {
+        self.globals.debugger.read().unwrap().clone()
+    }
+
+    pub(crate) fn add_thread(&self, thread: Thread) {
+        let mut threads = self.globals.threads.lock().unwrap();
+        threads.retain(|thread| !thread.is_joined());
+        threads.push(thread);
+    }
+
+    /// Waits for every thread spawned from Lisp that hasn't been joined,
+    /// including the ones they start meanwhile, and returns their errors.
+    ///
+    /// ```
+    /// let interpreter = lisp::Interpreter::new();
+    /// interpreter.eval_str("(thread/spawn (lambda () (car 1)))").unwrap();
+    /// let errors = interpreter.join_threads();
+    /// assert_eq!(errors[0].to_string(), "car: expected list, got integer 1");
+    /// ```
+    pub fn join_threads(&self) -> Vec<LispError> {
+        let mut errors = vec![];
+        loop {
+            let threads = std::mem::take(&mut *self.globals.threads.lock().unwrap());
+            if threads.is_empty() {
+                return errors;
+            }
+            for thread in threads.iter().filter(|thread| !thread.is_joined()) {
+                if let Err(err) = thread.join() {
+                    errors.push(err);
+                }
+            }
+        }
+    }
+
+    /// The cell for a global, if anything defined or declared it.
+    pub(crate) fn find_cell(&self, symbol: Sym) -> Option<Arc<Global>> {
+        self.globals.table.load().get(&symbol).cloned()
+    }
+
+    /// The cell for a global, created unbound if nothing defined it yet.
+    pub(crate) fn global_cell(&self, symbol: Sym) -> Arc<Global> {
+        if let Some(cell) = self.globals.table.load().get(&symbol) {
+            return cell.clone();
+        }
+        let cell = Arc::new(Global::new(symbol, self.globals.id, None));
+        // Another thread may add the same name meanwhile, its cell wins.
+        let table = self.globals.table.rcu(|table| {
+            let mut table = SymMap::clone(table);
+            table.entry(symbol).or_insert_with(|| cell.clone());
+            table
+        });
+        match table.get(&symbol) {
+            Some(cell) => cell.clone(),
+            None => cell,
+        }
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Interpreter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Interpreter {}", self.globals.id)
+    }
+}
@@ -0,0 +1,66 @@
+//! A small Lisp interpreter meant to be embedded in Rust programs.
+//!
+//! ```
+//! use lisp::{Exp, Interpreter};
+//!
+//! let interpreter = Interpreter::new();
+//! interpreter.register_fn("greet", |name: String| format!("hello {name}"));
+//! interpreter
+//!     .eval_str("(defun twice (f x) (f (f x)))")
+//!     .unwrap();
+//! let res = interpreter.eval_str("(twice (lambda (x) (* x 3)) 2)").unwrap();
+//! assert_eq!(res, Exp::Num(18));
+//! assert_eq!(
+//!     interpreter.eval_str("(greet \"lisp\")").unwrap(),
+//!     Exp::Str("hello lisp".into())
+//! );
+//! ```
+//!
+//! Each Interpreter has its own globals, so separate scripts can't see each
+//! other's definitions. For one-off evaluation there is eval_str:
+//!
+//! ```
+//! assert_eq!(lisp::eval_str("(+ 1 2 3)").unwrap(), lisp::Exp::Num(6));
+//! assert!(lisp::eval_str("(undefined-function)").is_err());
+//! ```
+
+mod atom;
+mod backtrace;
+mod builtins;
+mod cancel;
+mod channel;
+mod condition;
+mod convert;
+mod dynamic;
+mod env;
+mod error;
+mod eval;
+pub mod exp;
+mod future;
+mod interpreter;
+mod limits;
+mod parser;
+mod pool;
+mod resolve;
+#[cfg(feature = "serde")]
+pub mod serde_exp;
+mod stm;
+#[cfg(test)]
+mod tests;
+mod thread;
+mod tokenizer;
+
+pub use cancel::CancelToken;
+pub use condition::{Debugger, RestartInfo};
+pub use convert::{FromExp, IntoExp, IntoLispResult, IntoNative};
+pub use env::Env;
+pub use error::{ErrorKind, Frame, LispError, Span};
+pub use exp::{Exp, LispErr};
+pub use interpreter::{Interpreter, PURE_BUILTINS};
+pub use limits::Limits;
+pub use parser::parse;
+
+/// Evaluates src in a new interpreter.
+pub fn eval_str(src: &str) -> Result<Exp, LispErr> {
+    Interpreter::new().eval_str(src)
+}
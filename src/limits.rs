@@ -0,0 +1,126 @@
+use crate::error::{ErrorKind, LispError};
+use crate::exp::LispErr;
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// Resource budgets for running code that isn't trusted. The meter of an
+// interpreter goes along with its evaluations into the threads and futures
+// they start. Allocations only count up, the limit on them is checked at the
+// next step, and once a limit is hit every later step fails too.
+
+/// Bounds on what evaluation in an interpreter may use, None for no bound.
+///
+/// ```
+/// use lisp::Limits;
+///
+/// let interpreter = lisp::Interpreter::new();
+/// interpreter.set_limits(Limits {
+///     max_steps: Some(100),
+///     ..Limits::default()
+/// });
+/// let err = interpreter.eval_str("(defun loop () (loop)) (loop)").unwrap_err();
+/// assert_eq!(err.to_string(), "Resource limit exceeded: more than 100 steps");
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// Forms evaluated.
+    pub max_steps: Option<u64>,
+    /// Cons cells made plus bytes of strings made.
+    pub max_allocation: Option<u64>,
+    /// Threads started with thread/spawn.
+    pub max_threads: Option<u64>,
+}
+
+/// What evaluation has used of its limits so far.
+pub(crate) struct Meter {
+    limits: Limits,
+    steps: AtomicU64,
+    allocated: AtomicU64,
+    threads: AtomicU64,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<Meter>>> = const { RefCell::new(None) };
+}
+
+fn check(used: u64, limit: Option<u64>, resource: &'static str) -> Result<(), LispErr> {
+    match limit {
+        Some(limit) if used > limit => {
+            Err(LispError::new(ErrorKind::ResourceLimit { resource, limit }))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl Meter {
+    pub(crate) fn new(limits: Limits) -> Arc<Meter> {
+        Arc::new(Meter {
+            limits,
+            steps: AtomicU64::new(0),
+            allocated: AtomicU64::new(0),
+            threads: AtomicU64::new(0),
+        })
+    }
+
+    fn step(&self) -> Result<(), LispErr> {
+        let steps = self.steps.fetch_add(1, Ordering::Relaxed) + 1;
+        check(steps, self.limits.max_steps, "steps")?;
+        let allocated = self.allocated.load(Ordering::Relaxed);
+        check(
+            allocated,
+            self.limits.max_allocation,
+            "cells and string bytes",
+        )
+    }
+}
+
+fn with_meter<T>(f: impl FnOnce(&Meter) -> T) -> Option<T> {
+    CURRENT.with(|current| current.borrow().as_deref().map(f))
+}
+
+/// The meter evaluation on this thread charges, if any.
+pub(crate) fn current() -> Option<Arc<Meter>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+// Puts the meter back when dropped.
+struct Restore(Option<Arc<Meter>>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let saved = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = saved);
+    }
+}
+
+/// Runs f charging meter for what it does on this thread.
+pub(crate) fn metered<T>(meter: Option<Arc<Meter>>, f: impl FnOnce() -> T) -> T {
+    let _restore = Restore(CURRENT.with(|current| current.replace(meter)));
+    f()
+}
+
+/// Runs f without the meter of the code around it.
+pub(crate) fn isolated<T>(f: impl FnOnce() -> T) -> T {
+    metered(None, f)
+}
+
+/// Counts an evaluation step, failing if a limit has been passed.
+pub(crate) fn step() -> Result<(), LispErr> {
+    with_meter(Meter::step).unwrap_or(Ok(()))
+}
+
+/// Counts cons cells or string bytes being made.
+pub(crate) fn allocate(amount: usize) {
+    with_meter(|meter| meter.allocated.fetch_add(amount as u64, Ordering::Relaxed));
+}
+
+/// Counts a thread about to be started, failing if there are too many.
+pub(crate) fn start_thread() -> Result<(), LispErr> {
+    with_meter(|meter| {
+        let threads = meter.threads.fetch_add(1, Ordering::Relaxed) + 1;
+        check(threads, meter.limits.max_threads, "threads")
+    })
+    .unwrap_or(Ok(()))
+}
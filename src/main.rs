@@ -1,81 +1,150 @@
-use std::sync::Arc;
-
-pub mod env;
-pub mod eval;
-pub mod exp;
-pub mod parser;
-pub mod tests;
-pub mod tokenizer;
-
-use env::Env;
-use eval::eval;
-use parser::parse_tokens;
-use tokenizer::tokenize;
-
-pub fn main() {
-    let program = r##"(progn
-
-   (print (* (* 1 1 1 (+ 1 1) 1 1) (* (* 1 1) (* 1 1))))
-
-   (def identity (lambda (t) t))
-
-   (defun fibonacci (N)
-       (if (or (= N 0) (= N 1))
-           1
-           (+ (fibonacci (- N 1)) (fibonacci (- N 2)))))
-    (defun fibonacci-with-let (N)
-        (if (or (= N 0) (= N 1))
-            1
-            (let ((f1 (fibonacci (- N 1)))
-                  (f2 (fibonacci (- N 2))))
-                 (+ f1 f2))))
-
-   (print fibonacci)
-   (print (let () 2))
-
-   (print (let ((a 10)
-                (b 100))
-              (+ a b)))
-
-   (defun foreach (list fn)
-        (if list
-           (progn
-             (fn (car list))
-             (foreach (cdr list) fn))
-           (print 1234)))
-
-   (foreach (list 1 2 3 4 5 6) (lambda (x) (print x)))
-
-   (print (cdr (list 1 2 3 4)))
-   (print (car (list 1 2 3 4)))
-
-   (defun x (l) (print (identity l)) (x (+ l 1)))
-
-   (thread/spawn (lambda ()
-                     (print "hello from the thread")
-                     (dotimes 10 (lambda (i) (print "hiii")))))
-
-   (thread/spawn (lambda ()
-                     (print "hello from the other thread")
-                     (dotimes 10 (lambda (i) (print "hooo")))))
-
-   (dotimes 10 (lambda (x)
-       (thread/spawn (lambda ()
-                        (print "MORE THREADS")
-                        (dotimes 10 (lambda (i) (print x)))))))
-
-   (assert (= (fibonacci 5) (fibonacci-with-let 5)))
-
-   (fibonacci 5)
-   )"##;
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+
+use lisp::{CancelToken, ErrorKind, Exp, Interpreter, LispError, RestartInfo};
+
+// Runs the file given as argument, the program read from stdin, or a REPL
+// when stdin is a terminal. Threads the program started are waited for
+// before exiting, unless --no-wait is given.
+pub fn main() -> ExitCode {
+    let mut wait = true;
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--no-wait" => wait = false,
+            _ => path = Some(arg),
+        }
+    }
+
+    let interpreter = Interpreter::new();
+    let src = match path {
+        Some(path) => {
+            std::fs::read_to_string(&path).map_err(|err| format!("Can't read {path}: {err}"))
+        }
+        None if std::io::stdin().is_terminal() => {
+            repl(&interpreter);
+            if wait {
+                join_threads(&interpreter);
+            }
+            return ExitCode::SUCCESS;
+        }
+        None => {
+            let mut src = String::new();
+            std::io::stdin()
+                .read_to_string(&mut src)
+                .map(|_| src)
+                .map_err(|err| format!("Can't read stdin: {err}"))
+        }
+    };
+    let src = match src {
+        Ok(src) => src,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let res = interpreter.eval_str(&src);
+    let threads_ok = !wait || join_threads(&interpreter);
+    match res {
+        Ok(res) => {
+            println!("Result: {res}");
+            if threads_ok {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err.report());
+            ExitCode::FAILURE
+        }
+    }
+}
 
+// Waits for the threads nobody joined, returns whether they all succeeded.
+fn join_threads(interpreter: &Interpreter) -> bool {
+    let errors = interpreter.join_threads();
+    for err in &errors {
+        eprintln!("Error in thread: {}", err.report());
+    }
+    errors.is_empty()
+}
 
-    let tokens = tokenize(program.into());
+fn read_line() -> Option<String> {
+    let mut line = String::new();
+    match std::io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line),
+    }
+}
 
-    let mut iter = tokens.iter().peekable();
-    let tree = parse_tokens(&mut iter).unwrap();
+fn prompt(text: &str) {
+    eprint!("{text}");
+    let _ = std::io::stderr().flush();
+}
 
-    let res = eval(&tree, &Arc::new(Env::new())).unwrap();
+fn repl(interpreter: &Interpreter) {
+    interpreter.set_debugger(choose_restart);
+    // Ctrl-C cancels the evaluation that is running, with the threads it
+    // started, or quits at the prompt.
+    let running: Arc<Mutex<Option<CancelToken>>> = Arc::default();
+    let interrupted = running.clone();
+    let _ = ctrlc::set_handler(move || match &*interrupted.lock().unwrap() {
+        Some(token) => token.cancel(),
+        None => std::process::exit(130),
+    });
+    let mut src = String::new();
+    loop {
+        prompt(if src.is_empty() { "> " } else { "  " });
+        let Some(line) = read_line() else {
+            break;
+        };
+        src += &line;
+        // Keep reading until the forms are complete.
+        if let Err(err) = lisp::parse(&src) {
+            if matches!(err.kind(), ErrorKind::Parse(msg) if msg.starts_with("Unexpected EOF")) {
+                continue;
+            }
+        }
+        let token = CancelToken::new();
+        *running.lock().unwrap() = Some(token.clone());
+        let res = token.run(|| interpreter.eval_str(&src));
+        *running.lock().unwrap() = None;
+        match res {
+            Ok(res) => println!("{res}"),
+            Err(err) => eprintln!("Error: {}", err.report()),
+        }
+        src.clear();
+    }
+}
 
-    println!("Result: {res}");
+// Lets the user pick one of the active restarts when an error isn't handled.
+fn choose_restart(
+    interpreter: &Interpreter,
+    err: &LispError,
+    restarts: &[RestartInfo],
+) -> Option<(usize, Vec<Exp>)> {
+    eprintln!("Error: {err}");
+    eprintln!("Restarts:");
+    for (i, restart) in restarts.iter().enumerate() {
+        let params: Vec<String> = restart.params.iter().map(|p| p.to_string()).collect();
+        eprintln!("  {i}: {} {}", restart.name, params.join(" "));
+    }
+    prompt("Restart number, or nothing to unwind: ");
+    let index: usize = read_line()?.trim().parse().ok()?;
+    let restart = restarts.get(index)?;
+    let mut args = vec![];
+    for param in &restart.params {
+        prompt(&format!("{param}: "));
+        match interpreter.eval_str(&read_line()?) {
+            Ok(value) => args.push(value),
+            Err(err) => {
+                eprintln!("Error: {err}");
+                return None;
+            }
+        }
+    }
+    Some((index, args))
 }
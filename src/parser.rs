@@ -1,46 +1,95 @@
 use std::iter::Peekable;
 
+use crate::exp::character::char_from_name;
+use crate::error::LispError;
 use crate::exp::*;
+use crate::tokenizer::{tokenize, Token, TokenKind};
 
-pub fn parse_tokens<'a>(
-    tokens: &mut Peekable<impl Iterator<Item = &'a String>>,
+/// Reads every form in src.
+pub fn parse(src: &str) -> Result<Vec<Exp>, LispErr> {
+    let tokens = tokenize(src.into());
+    let mut iter = tokens.iter().peekable();
+    let mut forms = vec![];
+    while iter.peek().is_some() {
+        forms.push(parse_tokens(&mut iter)?);
+    }
+    Ok(forms)
+}
+
+pub(crate) fn parse_tokens<'a>(
+    tokens: &mut Peekable<impl Iterator<Item = &'a Token>>,
 ) -> Result<Exp, LispErr> {
     let token = match tokens.next() {
         Some(token) => token,
-        None => return Err("Unexpected EOF while parsing".into()),
+        None => return Err(LispError::parse("Unexpected EOF while parsing", None)),
     };
-    match token.as_str() {
-        "\"" => {
-            let mut list: Vec<String> = vec![];
-            while let Some(token) = tokens.next() {
-                if token == "\"" {
-                    break;
-                }
-                list.push(token.to_string());
-            }
-            // discard "
-            let string = list.join(" ");
-            Ok(Str(string))
-
-        },
+    let span = Some(token.span);
+    let eof = || LispError::parse("Unexpected EOF while parsing", span);
+    match token.kind {
+        TokenKind::Str => return Ok(Str(token.text.clone())),
+        TokenKind::Unterminated => return Err(LispError::parse("Unterminated string", span)),
+        TokenKind::Atom => {}
+    }
+    match token.text.as_str() {
         "(" => {
             let mut list = vec![];
-            while *tokens.peek().unwrap() != ")" {
+            while **tokens.peek().ok_or_else(eof)? != *")" {
                 list.push(parse_tokens(tokens)?);
             }
             // discard )
             let _ = tokens.next();
-            Ok(Vector(list))
+            Ok(Vector(Form::new(list, span)))
+        }
+        // {k v ...} and #{...} read as calls to the constructors, so keys
+        // and values get evaluated like any other arguments.
+        "{" | "#{" => {
+            let constructor = if token == "{" { "hash-map" } else { "hash-set" };
+            let mut list = vec![Symbol(Sym::intern(constructor))];
+            while **tokens.peek().ok_or_else(eof)? != *"}" {
+                list.push(parse_tokens(tokens)?);
+            }
+            // discard }
+            let _ = tokens.next();
+            Ok(Vector(Form::new(list, span)))
+        }
+        // @x reads as (deref x).
+        "@" => {
+            if tokens.peek().is_none() {
+                return Err(eof());
+            }
+            let items = vec![Symbol(Sym::intern("deref")), parse_tokens(tokens)?];
+            Ok(Vector(Form::new(items, span)))
         }
-        ")" => Err("Unexpected )".into()),
-        token => Ok(atom(token)),
+        ")" => Err(LispError::parse("Unexpected )", span)),
+        "}" => Err(LispError::parse("Unexpected }", span)),
+        text => atom(text).map_err(|err| err.with_span(span)),
     }
 }
 
-fn atom<'a>(token: &'a str) -> Exp {
-    if let Ok(num) = token.parse::<i64>() {
-        Num(num)
+fn atom(token: &str) -> Result<Exp, LispErr> {
+    if let Some(name) = token.strip_prefix("#\\") {
+        parse_char(name).map(Char)
+    } else if let Some(name) = token.strip_prefix(':').filter(|name| !name.is_empty()) {
+        Ok(Keyword(Keyword::intern(name)))
+    } else if let Ok(num) = token.parse::<i64>() {
+        Ok(Num(num))
     } else {
-        Symbol(token.to_string())
+        Ok(Symbol(Sym::intern(token)))
+    }
+}
+
+fn parse_char(name: &str) -> Result<char, LispErr> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(c);
+    }
+    if let Some(c) = char_from_name(name) {
+        return Ok(c);
+    }
+    if let Some(hex) = name.strip_prefix('x') {
+        if let Some(c) = u32::from_str_radix(hex, 16).ok().and_then(char::from_u32) {
+            return Ok(c);
+        }
     }
+    Err(LispError::parse(format!("Invalid character literal #\\{name}"), None))
 }
@@ -0,0 +1,149 @@
+use crate::backtrace;
+use crate::cancel;
+use crate::condition;
+use crate::dynamic;
+use crate::limits;
+use crate::stm;
+
+use std::cell::RefCell;
+use std::sync::{Condvar, Mutex};
+
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
+use lazy_static::lazy_static;
+
+// A fixed set of worker threads, one per core, shared by the whole process.
+// Jobs spawned by a worker go to its own deque, the rest to a shared queue,
+// and idle workers steal from the others. A worker waiting for a result
+// runs other jobs meanwhile, so jobs waiting on jobs can't use up the pool.
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Pool {
+    injector: Injector<Job>,
+    stealers: Vec<Stealer<Job>>,
+    sleep: Mutex<()>,
+    wake: Condvar,
+}
+
+lazy_static! {
+    static ref POOL: Pool = Pool::start();
+}
+
+thread_local! {
+    // The deque of the worker running on this thread, if it is one.
+    static LOCAL: RefCell<Option<Worker<Job>>> = const { RefCell::new(None) };
+}
+
+impl Pool {
+    fn start() -> Pool {
+        let size = std::thread::available_parallelism().map_or(4, |n| n.get());
+        let workers: Vec<Worker<Job>> = (0..size).map(|_| Worker::new_lifo()).collect();
+        let stealers = workers.iter().map(Worker::stealer).collect();
+        for (index, worker) in workers.into_iter().enumerate() {
+            std::thread::Builder::new()
+                .name(format!("lisp-worker-{index}"))
+                .spawn(move || {
+                    LOCAL.with(|local| *local.borrow_mut() = Some(worker));
+                    // Waits until start has returned.
+                    POOL.work();
+                })
+                .expect("can't start the worker threads");
+        }
+        Pool {
+            injector: Injector::new(),
+            stealers,
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+        }
+    }
+
+    fn push(&self, job: Job) {
+        let job = LOCAL.with(|local| match &*local.borrow() {
+            Some(worker) => {
+                worker.push(job);
+                None
+            }
+            None => Some(job),
+        });
+        if let Some(job) = job {
+            self.injector.push(job);
+        }
+        // Taking the lock first means a worker that just found nothing to
+        // do is already waiting and gets the notification.
+        let _sleep = self.sleep.lock().unwrap();
+        self.wake.notify_one();
+    }
+
+    fn find_job(&self) -> Option<Job> {
+        if let Some(job) = LOCAL.with(|local| local.borrow().as_ref()?.pop()) {
+            return Some(job);
+        }
+        loop {
+            let mut retry = false;
+            let queues = std::iter::once(self.injector.steal())
+                .chain(self.stealers.iter().map(Stealer::steal));
+            for steal in queues {
+                match steal {
+                    Steal::Success(job) => return Some(job),
+                    Steal::Retry => retry = true,
+                    Steal::Empty => {}
+                }
+            }
+            if !retry {
+                return None;
+            }
+        }
+    }
+
+    fn has_jobs(&self) -> bool {
+        !self.injector.is_empty() || self.stealers.iter().any(|stealer| !stealer.is_empty())
+    }
+
+    fn work(&self) {
+        loop {
+            if let Some(job) = self.find_job() {
+                run(job);
+                continue;
+            }
+            let sleep = self.sleep.lock().unwrap();
+            if !self.has_jobs() {
+                drop(self.wake.wait(sleep).unwrap());
+            }
+        }
+    }
+}
+
+// Jobs start from a clean slate even when a waiting worker runs them in the
+// middle of another one.
+fn run(job: Job) {
+    let job = || cancel::isolated(job);
+    let job = || limits::isolated(job);
+    let job = || stm::isolated(job);
+    let job = || dynamic::isolated(job);
+    let job = || backtrace::isolated(job);
+    condition::isolated(job);
+}
+
+/// Runs job on one of the worker threads.
+pub fn spawn(job: impl FnOnce() + Send + 'static) {
+    POOL.push(Box::new(job));
+}
+
+/// Whether this thread is one of the workers.
+pub fn is_worker() -> bool {
+    LOCAL.with(|local| local.borrow().is_some())
+}
+
+/// On a worker, runs one pending job if there is any. Returns whether it did.
+pub(crate) fn run_pending() -> bool {
+    if !is_worker() {
+        return false;
+    }
+    match POOL.find_job() {
+        Some(job) => {
+            run(job);
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,119 @@
+use crate::exp::var::Address;
+use crate::exp::*;
+use crate::interpreter::Interpreter;
+
+// Lexical addressing. When a lambda is created its body is rewritten so each
+// reference to a variable of a frame the lambda builds itself, its arguments
+// or a let inside it, becomes a VarRef holding Address::Local(depth, index),
+// the position of the value in the chain of frames. Lambdas run on top of
+// the caller's frames, so free variables are still looked up by name at
+// runtime. The ones that already have a global cell become an
+// Address::Global pointing at it, which is read when no frame binds the
+// name. Forms that bind variables in ways this pass doesn't know about are
+// left alone.
+
+/// The frames that will be visible when the resolved code runs.
+pub enum Scope<'a> {
+    /// A frame that doesn't exist yet, e.g. the arguments of the lambda being built.
+    Frame(&'a [Sym], &'a Scope<'a>),
+    /// The frames of whoever calls the lambda, unknown until it runs.
+    Caller(&'a Interpreter),
+}
+
+impl Scope<'_> {
+    fn interpreter(&self) -> &Interpreter {
+        let mut scope = self;
+        loop {
+            match scope {
+                Scope::Frame(_, upper) => scope = upper,
+                Scope::Caller(interpreter) => return interpreter,
+            }
+        }
+    }
+
+    fn lookup(&self, sym: Sym) -> Option<(u32, u32)> {
+        let mut depth = 0;
+        let mut scope = self;
+        loop {
+            match scope {
+                Scope::Frame(names, upper) => {
+                    if let Some(index) = names.iter().rposition(|name| *name == sym) {
+                        return Some((depth, index as u32));
+                    }
+                    scope = upper;
+                    depth += 1;
+                }
+                Scope::Caller(_) => return None,
+            }
+        }
+    }
+}
+
+pub fn resolve(exp: &Exp, scope: &Scope) -> Exp {
+    match exp {
+        Symbol(sym) => match scope.lookup(*sym) {
+            Some((depth, index)) => Var(VarRef(Address::Local(depth, index, *sym))),
+            None => match scope.interpreter().find_cell(*sym) {
+                Some(cell) => Var(VarRef(Address::Global(cell))),
+                None => Symbol(*sym),
+            },
+        },
+        Vector(form) => resolve_form(form, scope),
+        other => other.clone(),
+    }
+}
+
+pub fn resolve_many(exps: &[Exp], scope: &Scope) -> Vec<Exp> {
+    exps.iter().map(|exp| resolve(exp, scope)).collect()
+}
+
+fn resolve_form(form: &Form, scope: &Scope) -> Exp {
+    // Rebuilt forms keep the span of the original for error messages.
+    let rebuilt = |items| Vector(Form::new(items, form.span()));
+    let Some(Symbol(head)) = form.first() else {
+        return rebuilt(resolve_many(form, scope));
+    };
+    if scope.lookup(*head).is_some() {
+        return rebuilt(resolve_many(form, scope));
+    }
+    let value = scope
+        .interpreter()
+        .find_cell(*head)
+        .and_then(|cell| cell.peek());
+    match value {
+        Some(Macro(_) | Special(_)) => match &*head.name() {
+            // Macros that evaluate all their arguments in the current scope.
+            "progn" | "if" => rebuilt(resolve_many(form, scope)),
+            "let" => resolve_let(form, scope).map_or_else(|| Vector(form.clone()), rebuilt),
+            // lambda resolves its own body when the function is created.
+            _ => Vector(form.clone()),
+        },
+        // Function calls, including functions that aren't defined yet.
+        _ => rebuilt(resolve_many(form, scope)),
+    }
+}
+
+// None if the let is malformed, it will fail with a proper error when run.
+fn resolve_let(form: &[Exp], scope: &Scope) -> Option<Vec<Exp>> {
+    let [head, Vector(bindings), body @ ..] = form else {
+        return None;
+    };
+    let mut names = vec![];
+    let mut resolved = vec![];
+    for binding in bindings {
+        let Vector(binding) = binding else {
+            return None;
+        };
+        let [Symbol(name), value] = binding.as_slice() else {
+            return None;
+        };
+        names.push(*name);
+        // Values are evaluated outside the let frame.
+        let items = vec![Symbol(*name), resolve(value, scope)];
+        resolved.push(Vector(Form::new(items, binding.span())));
+    }
+    let let_scope = Scope::Frame(&names, scope);
+    let mut let_form = vec![head.clone(), Vector(Form::new(resolved, bindings.span()))];
+    let_form.extend(resolve_many(body, &let_scope));
+    Some(let_form)
+}
@@ -0,0 +1,809 @@
+use crate::convert::{FromExp, IntoLispResult};
+use crate::exp::list::{dolist, list_from_slice};
+use crate::exp::*;
+
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::ser::{self, Serialize};
+use serde::{forward_to_deserialize_any, Deserialize};
+
+use std::fmt;
+
+// Conversions between Exp and any serde compatible type. Structs become maps
+// with keyword keys, sequences and tuples become lists, Option::None and ()
+// become nil, and enums are tagged the serde way: a unit variant is the
+// keyword :Variant, other variants are a map {:Variant contents}. Going the
+// other way keywords and symbols become strings, so a map with keyword keys
+// that isn't read back as a struct comes back with string keys.
+
+#[derive(Debug)]
+pub struct SerdeError(String);
+
+impl fmt::Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SerdeError {}
+
+impl ser::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeError(msg.to_string())
+    }
+}
+
+impl de::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeError(msg.to_string())
+    }
+}
+
+impl From<SerdeError> for LispErr {
+    fn from(err: SerdeError) -> Self {
+        err.0.into()
+    }
+}
+
+/// Converts any serializable Rust value to an Exp.
+pub fn to_exp<T: Serialize + ?Sized>(value: &T) -> Result<Exp, SerdeError> {
+    value.serialize(ExpSerializer)
+}
+
+/// Builds a Rust value out of an Exp.
+pub fn from_exp<T: DeserializeOwned>(exp: &Exp) -> Result<T, SerdeError> {
+    T::deserialize(ExpDeserializer(exp))
+}
+
+/// Wrapper to pass serde types to and from functions registered with
+/// Interpreter::register_fn.
+pub struct Serde<T>(pub T);
+
+impl<T: DeserializeOwned> FromExp for Serde<T> {
+    fn from_exp(exp: &Exp) -> Result<Self, LispErr> {
+        Ok(Serde(from_exp(exp)?))
+    }
+}
+
+// Not IntoExp, since serializing can fail and the script should get the
+// error rather than a value.
+impl<T: Serialize> IntoLispResult for Serde<T> {
+    fn into_lisp_result(self) -> Result<Exp, LispErr> {
+        Ok(to_exp(&self.0)?)
+    }
+}
+
+impl<T: Serialize> IntoLispResult for Result<Serde<T>, LispErr> {
+    fn into_lisp_result(self) -> Result<Exp, LispErr> {
+        self?.into_lisp_result()
+    }
+}
+
+fn keyword(name: &str) -> Exp {
+    Keyword(Keyword::intern(name))
+}
+
+fn tagged(variant: &str, value: Exp) -> Exp {
+    Map(Map::new().insert(keyword(variant), value))
+}
+
+fn list(items: Vec<Exp>) -> Exp {
+    List(list_from_slice(&items))
+}
+
+struct ExpSerializer;
+
+impl ser::Serializer for ExpSerializer {
+    type Ok = Exp;
+    type Error = SerdeError;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Exp, SerdeError> {
+        Ok(Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Exp, SerdeError> {
+        Ok(Num(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Exp, SerdeError> {
+        Ok(Num(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Exp, SerdeError> {
+        Ok(Num(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Exp, SerdeError> {
+        Ok(Num(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Exp, SerdeError> {
+        Ok(Num(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Exp, SerdeError> {
+        Ok(Num(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Exp, SerdeError> {
+        Ok(Num(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Exp, SerdeError> {
+        i64::try_from(v)
+            .map(Num)
+            .map_err(|_| SerdeError(format!("{v} doesn't fit in an integer")))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Exp, SerdeError> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Exp, SerdeError> {
+        Err(SerdeError(format!(
+            "{v}: floating point numbers are not supported"
+        )))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Exp, SerdeError> {
+        Ok(Char(v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Exp, SerdeError> {
+        Ok(Str(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Exp, SerdeError> {
+        Ok(list(v.iter().map(|b| Num((*b).into())).collect()))
+    }
+
+    fn serialize_none(self) -> Result<Exp, SerdeError> {
+        Ok(List(None))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Exp, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Exp, SerdeError> {
+        Ok(List(None))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Exp, SerdeError> {
+        Ok(List(None))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Exp, SerdeError> {
+        Ok(keyword(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Exp, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Exp, SerdeError> {
+        Ok(tagged(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, SerdeError> {
+        Ok(SeqSerializer {
+            variant: None,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, SerdeError> {
+        Ok(SeqSerializer {
+            variant: Some(variant),
+            items: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer, SerdeError> {
+        Ok(MapSerializer {
+            variant: None,
+            map: Map::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<MapSerializer, SerdeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<MapSerializer, SerdeError> {
+        Ok(MapSerializer {
+            variant: Some(variant),
+            map: Map::new(),
+            key: None,
+        })
+    }
+}
+
+struct SeqSerializer {
+    variant: Option<&'static str>,
+    items: Vec<Exp>,
+}
+
+impl SeqSerializer {
+    fn finish(self) -> Exp {
+        match self.variant {
+            Some(variant) => tagged(variant, list(self.items)),
+            None => list(self.items),
+        }
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Exp;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.items.push(to_exp(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Exp, SerdeError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Exp;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Exp, SerdeError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Exp;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Exp, SerdeError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = Exp;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Exp, SerdeError> {
+        Ok(self.finish())
+    }
+}
+
+struct MapSerializer {
+    variant: Option<&'static str>,
+    map: Map,
+    key: Option<Exp>,
+}
+
+impl MapSerializer {
+    fn finish(self) -> Exp {
+        match self.variant {
+            Some(variant) => tagged(variant, Map(self.map)),
+            None => Map(self.map),
+        }
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Exp;
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerdeError> {
+        self.key = Some(to_exp(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| SerdeError("map value without a key".into()))?;
+        self.map = self.map.insert(key, to_exp(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Exp, SerdeError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Exp;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        self.map = self.map.insert(keyword(key), to_exp(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Exp, SerdeError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+    type Ok = Exp;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Exp, SerdeError> {
+        Ok(self.finish())
+    }
+}
+
+struct ExpDeserializer<'a>(&'a Exp);
+
+fn list_items(list: &List) -> Result<Vec<Exp>, SerdeError> {
+    let mut items = vec![];
+    dolist(list, |item| {
+        items.push(item.clone());
+        Ok(())
+    })
+    .map_err(|err| SerdeError(err.to_string()))?;
+    Ok(items)
+}
+
+struct SeqDeserializer {
+    items: std::vec::IntoIter<Exp>,
+}
+
+impl<'de> SeqAccess<'de> for SeqDeserializer {
+    type Error = SerdeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, SerdeError> {
+        match self.items.next() {
+            Some(item) => seed.deserialize(ExpDeserializer(&item)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+struct MapDeserializer {
+    entries: std::vec::IntoIter<(Exp, Exp)>,
+    value: Option<Exp>,
+}
+
+impl<'de> MapAccess<'de> for MapDeserializer {
+    type Error = SerdeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, SerdeError> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(ExpDeserializer(&key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, SerdeError> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| SerdeError("map key without a value".into()))?;
+        seed.deserialize(ExpDeserializer(&value))
+    }
+}
+
+struct EnumDeserializer<'a> {
+    variant: String,
+    value: Option<&'a Exp>,
+}
+
+impl<'de, 'a> EnumAccess<'de> for EnumDeserializer<'a> {
+    type Error = SerdeError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), SerdeError> {
+        let variant = seed.deserialize(self.variant.as_str().into_deserializer())?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a> VariantAccess<'de> for EnumDeserializer<'a> {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), SerdeError> {
+        match self.value {
+            None | Some(List(None)) => Ok(()),
+            Some(other) => Err(SerdeError(format!(
+                "expected unit variant {}, got {other}",
+                self.variant
+            ))),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, SerdeError> {
+        match self.value {
+            Some(value) => seed.deserialize(ExpDeserializer(value)),
+            None => Err(SerdeError(format!(
+                "variant {} needs a value",
+                self.variant
+            ))),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        match self.value {
+            Some(value) => de::Deserializer::deserialize_seq(ExpDeserializer(value), visitor),
+            None => Err(SerdeError(format!("variant {} needs a list", self.variant))),
+        }
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        match self.value {
+            Some(value) => de::Deserializer::deserialize_map(ExpDeserializer(value), visitor),
+            None => Err(SerdeError(format!("variant {} needs a map", self.variant))),
+        }
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for ExpDeserializer<'a> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.0 {
+            Num(n) => visitor.visit_i64(*n),
+            Str(s) => visitor.visit_str(s),
+            Char(c) => visitor.visit_char(*c),
+            Bool(b) => visitor.visit_bool(*b),
+            Keyword(k) => visitor.visit_str(k.name()),
+            Symbol(s) => visitor.visit_str(&s.name()),
+            List(None) => visitor.visit_unit(),
+            List(list) => visitor.visit_seq(SeqDeserializer {
+                items: list_items(list)?.into_iter(),
+            }),
+            Vector(items) => visitor.visit_seq(SeqDeserializer {
+                items: items.clone().into_iter(),
+            }),
+            Set(set) => visitor.visit_seq(SeqDeserializer {
+                items: set.iter().cloned().collect::<Vec<_>>().into_iter(),
+            }),
+            Map(map) => visitor.visit_map(MapDeserializer {
+                entries: map
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+                value: None,
+            }),
+            other => Err(SerdeError(format!(
+                "can't deserialize {} {other}",
+                other.type_name()
+            ))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.0 {
+            List(None) => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.0 {
+            // nil is also the empty list
+            List(None) => visitor.visit_seq(SeqDeserializer {
+                items: vec![].into_iter(),
+            }),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.0 {
+            List(None) => visitor.visit_map(MapDeserializer {
+                entries: vec![].into_iter(),
+                value: None,
+            }),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        match self.0 {
+            Keyword(k) => visitor.visit_enum(EnumDeserializer {
+                variant: k.name().to_string(),
+                value: None,
+            }),
+            Str(s) => visitor.visit_enum(EnumDeserializer {
+                variant: s.clone(),
+                value: None,
+            }),
+            Map(map) if map.len() == 1 => {
+                let (tag, value) = map.iter().next().unwrap();
+                let variant = match tag {
+                    Keyword(k) => k.name().to_string(),
+                    Str(s) => s.clone(),
+                    other => return Err(SerdeError(format!("enum tag {other} is not a keyword"))),
+                };
+                visitor.visit_enum(EnumDeserializer {
+                    variant,
+                    value: Some(value),
+                })
+            }
+            other => Err(SerdeError(format!(
+                "expected :Variant or {{:Variant value}}, got {other}"
+            ))),
+        }
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.0 {
+            Str(s) if s.chars().count() == 1 => visitor.visit_char(s.chars().next().unwrap()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 str string
+        bytes byte_buf unit unit_struct identifier ignored_any
+    }
+}
+
+/// Data values serialize with the same mapping used by to_exp, so an Exp can
+/// be written out with any serde format. Keywords and symbols are written as
+/// strings, so keyword map keys read back as string keys unless the map is
+/// read as a struct. Functions and other runtime values can't be serialized.
+impl Serialize for Exp {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use ser::{Error, SerializeMap, SerializeSeq};
+
+        match self {
+            Num(n) => serializer.serialize_i64(*n),
+            Str(s) => serializer.serialize_str(s),
+            Char(c) => serializer.serialize_char(*c),
+            Bool(b) => serializer.serialize_bool(*b),
+            Keyword(k) => serializer.serialize_str(k.name()),
+            Symbol(s) => serializer.serialize_str(&s.name()),
+            List(None) => serializer.serialize_unit(),
+            List(list) => {
+                let items = list_items(list).map_err(S::Error::custom)?;
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in &items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Vector(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Set(set) => {
+                let mut seq = serializer.serialize_seq(Some(set.len()))?;
+                for item in set.iter() {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Map(map) => {
+                let mut out = serializer.serialize_map(Some(map.len()))?;
+                for (k, v) in map.iter() {
+                    out.serialize_entry(k, v)?;
+                }
+                out.end()
+            }
+            other => Err(S::Error::custom(format!(
+                "can't serialize {} {other}",
+                other.type_name()
+            ))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Exp {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Exp, D::Error> {
+        struct ExpVisitor;
+
+        impl<'de> Visitor<'de> for ExpVisitor {
+            type Value = Exp;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a value representable as a lisp expression")
+            }
+
+            fn visit_bool<E: de::Error>(self, v: bool) -> Result<Exp, E> {
+                Ok(Bool(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Exp, E> {
+                Ok(Num(v))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Exp, E> {
+                i64::try_from(v)
+                    .map(Num)
+                    .map_err(|_| E::custom(format!("{v} doesn't fit in an integer")))
+            }
+
+            fn visit_char<E: de::Error>(self, v: char) -> Result<Exp, E> {
+                Ok(Char(v))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Exp, E> {
+                Ok(Str(v.to_string()))
+            }
+
+            fn visit_unit<E: de::Error>(self) -> Result<Exp, E> {
+                Ok(List(None))
+            }
+
+            fn visit_none<E: de::Error>(self) -> Result<Exp, E> {
+                Ok(List(None))
+            }
+
+            fn visit_some<D: de::Deserializer<'de>>(self, d: D) -> Result<Exp, D::Error> {
+                Exp::deserialize(d)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Exp, A::Error> {
+                let mut items = vec![];
+                while let Some(item) = seq.next_element::<Exp>()? {
+                    items.push(item);
+                }
+                Ok(list(items))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Exp, A::Error> {
+                let mut map = Map::new();
+                while let Some((k, v)) = access.next_entry::<Exp, Exp>()? {
+                    map = map.insert(k, v);
+                }
+                Ok(Map(map))
+            }
+        }
+
+        deserializer.deserialize_any(ExpVisitor)
+    }
+}
@@ -0,0 +1,343 @@
+use crate::env::Env;
+use crate::error::{ErrorKind, LispError};
+use crate::eval::apply;
+use crate::exp::*;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use arc_swap::ArcSwap;
+
+// Software transactional memory. Every committed value of a ref is stamped
+// with the clock at the time of the commit. A transaction sees the refs as
+// they were when it started: reading one that was committed to since then
+// makes it start over. Commits happen one at a time, check that nothing the
+// transaction read has changed, and only then move the clock, so a
+// transaction never sees half of another one. No Lisp code runs while
+// committing, so a commit can't wait on another one. Reading a ref outside
+// of a transaction is a plain load and never waits.
+
+const MAX_RETRIES: usize = 10_000;
+
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+static NEXT_REF: AtomicU64 = AtomicU64::new(0);
+static COMMIT: Mutex<()> = Mutex::new(());
+
+struct Version {
+    value: Exp,
+    stamp: u64,
+}
+
+/// A shared value that is only changed inside dosync.
+pub struct Ref {
+    id: u64,
+    current: ArcSwap<Version>,
+}
+
+// What a transaction did to one ref.
+#[derive(Clone)]
+struct Entry {
+    target: Arc<Ref>,
+    // Stamp of the value it read, checked again on commit.
+    read: Option<u64>,
+    // The value inside the transaction.
+    value: Option<Exp>,
+    written: bool,
+    // Functions and arguments of commute, applied again on commit.
+    commutes: Vec<(Exp, Vec<Exp>)>,
+}
+
+#[derive(Clone)]
+struct Transaction {
+    read_point: u64,
+    // By id, the order refs are updated in.
+    entries: BTreeMap<u64, Entry>,
+}
+
+thread_local! {
+    static TRANSACTION: RefCell<Option<Transaction>> = const { RefCell::new(None) };
+}
+
+fn conflict() -> LispError {
+    LispError::new(ErrorKind::Conflict)
+}
+
+impl Ref {
+    pub fn new(value: Exp) -> Arc<Ref> {
+        Arc::new(Ref {
+            id: NEXT_REF.fetch_add(1, Ordering::Relaxed),
+            // Older than any transaction, all of them can read it.
+            current: ArcSwap::from_pointee(Version { value, stamp: 0 }),
+        })
+    }
+
+    /// The ref as a Lisp value.
+    pub fn to_exp(self: &Arc<Self>) -> Exp {
+        Object(Object::from_arc_with_type_name("ref", self.clone()))
+    }
+
+    /// The value in the current transaction, or the last committed one
+    /// outside of any.
+    pub fn deref(self: &Arc<Self>) -> Result<Exp, LispErr> {
+        if in_transaction() {
+            with_entry(self, read)
+        } else {
+            Ok(self.current.load().value.clone())
+        }
+    }
+
+    /// Sets the ref to (f value args...) in the current transaction.
+    pub fn alter(
+        self: &Arc<Self>,
+        function: &Exp,
+        args: &[Exp],
+        env: &Arc<Env>,
+    ) -> Result<Exp, LispErr> {
+        let value = self.deref_for("alter")?;
+        let new = apply(function, call_args(value, args), env)?;
+        self.set(new)
+    }
+
+    /// Like alter, but doesn't conflict with other transactions changing
+    /// the ref: f is applied again to whatever value the ref has on commit.
+    pub fn commute(
+        self: &Arc<Self>,
+        function: &Exp,
+        args: &[Exp],
+        env: &Arc<Env>,
+    ) -> Result<Exp, LispErr> {
+        let value = with_entry_in("commute", self, |entry, _| {
+            Ok(match &entry.value {
+                Some(value) => value.clone(),
+                None => entry.target.current.load().value.clone(),
+            })
+        })?;
+        let new = apply(function, call_args(value, args), env)?;
+        with_entry(self, |entry, _| {
+            // After a write the ref is checked on commit anyway.
+            if !entry.written {
+                entry.commutes.push((function.clone(), args.to_vec()));
+            }
+            entry.value = Some(new.clone());
+            Ok(new.clone())
+        })
+    }
+
+    /// Sets the ref to value in the current transaction.
+    pub fn ref_set(self: &Arc<Self>, value: Exp) -> Result<Exp, LispErr> {
+        self.deref_for("ref-set")?;
+        self.set(value)
+    }
+
+    fn deref_for(self: &Arc<Self>, name: &str) -> Result<Exp, LispErr> {
+        with_entry_in(name, self, |entry, read_point| {
+            if !entry.commutes.is_empty() {
+                return Err(format!("{name}: can't set a ref after commute").into());
+            }
+            read(entry, read_point)
+        })
+    }
+
+    fn set(self: &Arc<Self>, value: Exp) -> Result<Exp, LispErr> {
+        with_entry(self, |entry, _| {
+            entry.value = Some(value.clone());
+            entry.written = true;
+            Ok(value.clone())
+        })
+    }
+}
+
+fn call_args(value: Exp, args: &[Exp]) -> Vec<Exp> {
+    let mut call_args = vec![value];
+    call_args.extend_from_slice(args);
+    call_args
+}
+
+// The value of a ref as of the start of the transaction.
+fn read(entry: &mut Entry, read_point: u64) -> Result<Exp, LispErr> {
+    if let Some(value) = &entry.value {
+        return Ok(value.clone());
+    }
+    let current = entry.target.current.load();
+    if current.stamp > read_point {
+        return Err(conflict());
+    }
+    entry.read = Some(current.stamp);
+    entry.value = Some(current.value.clone());
+    Ok(current.value.clone())
+}
+
+pub fn in_transaction() -> bool {
+    TRANSACTION.with(|transaction| transaction.borrow().is_some())
+}
+
+fn with_entry<T>(
+    target: &Arc<Ref>,
+    f: impl FnOnce(&mut Entry, u64) -> Result<T, LispErr>,
+) -> Result<T, LispErr> {
+    with_entry_in("dosync", target, f)
+}
+
+// Runs f on the entry of target in the current transaction, name says who
+// needed one if there's none.
+fn with_entry_in<T>(
+    name: &str,
+    target: &Arc<Ref>,
+    f: impl FnOnce(&mut Entry, u64) -> Result<T, LispErr>,
+) -> Result<T, LispErr> {
+    TRANSACTION.with(|transaction| {
+        let mut transaction = transaction.borrow_mut();
+        let Some(transaction) = transaction.as_mut() else {
+            return Err(format!("{name} has to be called inside dosync").into());
+        };
+        let entry = transaction
+            .entries
+            .entry(target.id)
+            .or_insert_with(|| Entry {
+                target: target.clone(),
+                read: None,
+                value: None,
+                written: false,
+                commutes: vec![],
+            });
+        f(entry, transaction.read_point)
+    })
+}
+
+/// Runs body in a transaction, starting over when another transaction got
+/// in the way. Inside another transaction body just becomes part of it.
+pub fn dosync(
+    env: &Arc<Env>,
+    mut body: impl FnMut() -> Result<Exp, LispErr>,
+) -> Result<Exp, LispErr> {
+    if in_transaction() {
+        return body();
+    }
+    for tries in 0..MAX_RETRIES {
+        back_off(tries);
+        let _end = Isolated::with(Some(Transaction {
+            read_point: CLOCK.load(Ordering::Acquire),
+            entries: BTreeMap::new(),
+        }));
+        let res = body();
+        let transaction = TRANSACTION.with(|transaction| transaction.borrow_mut().take());
+        let res = match (res, transaction) {
+            (Ok(value), Some(transaction)) => commit(transaction, env).map(|()| value),
+            (res, _) => res,
+        };
+        match res {
+            Err(err) if matches!(err.kind(), ErrorKind::Conflict) => continue,
+            res => return res,
+        }
+    }
+    Err(format!("dosync: gave up after {MAX_RETRIES} conflicting tries").into())
+}
+
+// Waits a little before trying again, longer the more tries conflicted, so
+// the transactions in the way get to commit. Spins at first and gives up the
+// processor once that's not enough.
+fn back_off(tries: usize) {
+    match tries {
+        0 => {}
+        1..=6 => (0..1 << tries).for_each(|_| std::hint::spin_loop()),
+        _ => std::thread::yield_now(),
+    }
+}
+
+fn commit(transaction: Transaction, env: &Arc<Env>) -> Result<(), LispErr> {
+    for tries in 0..MAX_RETRIES {
+        back_off(tries);
+        // Commute functions are Lisp code that may start transactions of
+        // their own, so they run before taking the lock, on a copy of the
+        // transaction that any dosync inside them joins. Their results only
+        // count if the refs are still the versions they were applied to.
+        let (transaction, commuted) = commute_values(transaction.clone(), env)?;
+        let _commit = COMMIT.lock().unwrap_or_else(PoisonError::into_inner);
+        for entry in transaction.entries.values() {
+            if entry
+                .read
+                .is_some_and(|stamp| entry.target.current.load().stamp != stamp)
+            {
+                return Err(conflict());
+            }
+        }
+        if commuted
+            .iter()
+            .any(|(target, seen, _)| target.current.load().stamp != *seen)
+        {
+            continue;
+        }
+        let stamp = CLOCK.load(Ordering::Acquire) + 1;
+        let written = transaction.entries.values().filter(|entry| entry.written);
+        for entry in written {
+            if let Some(value) = &entry.value {
+                let version = Version {
+                    value: value.clone(),
+                    stamp,
+                };
+                entry.target.current.store(Arc::new(version));
+            }
+        }
+        for (target, _, value) in commuted {
+            target.current.store(Arc::new(Version { value, stamp }));
+        }
+        // Transactions starting from now on see all of it.
+        CLOCK.store(stamp, Ordering::Release);
+        return Ok(());
+    }
+    Err(conflict())
+}
+
+// A ref that was only commuted, the stamp of the version the functions were
+// applied to and the value they made.
+type Commuted = (Arc<Ref>, u64, Exp);
+
+// The new values of the commuted refs and the transaction as the functions
+// left it.
+fn commute_values(
+    transaction: Transaction,
+    env: &Arc<Env>,
+) -> Result<(Transaction, Vec<Commuted>), LispErr> {
+    let commuted: Vec<_> = transaction
+        .entries
+        .values()
+        .filter(|entry| !entry.written && !entry.commutes.is_empty())
+        .map(|entry| (entry.target.clone(), entry.commutes.clone()))
+        .collect();
+    let _inside = Isolated::with(Some(transaction));
+    let mut values = vec![];
+    for (target, commutes) in commuted {
+        let current = target.current.load_full();
+        let mut value = current.value.clone();
+        for (function, args) in &commutes {
+            value = apply(function, call_args(value, args), env)?;
+        }
+        values.push((target, current.stamp, value));
+    }
+    let transaction = TRANSACTION.with(|current| current.borrow_mut().take());
+    Ok((transaction.expect("commute left the transaction"), values))
+}
+
+// Swaps the transaction of this thread for another one until dropped.
+struct Isolated(Option<Transaction>);
+
+impl Isolated {
+    fn with(transaction: Option<Transaction>) -> Isolated {
+        Isolated(TRANSACTION.with(|current| current.replace(transaction)))
+    }
+}
+
+impl Drop for Isolated {
+    fn drop(&mut self) {
+        let saved = self.0.take();
+        TRANSACTION.with(|current| *current.borrow_mut() = saved);
+    }
+}
+
+/// Runs f outside of any transaction this thread is in.
+pub(crate) fn isolated<T>(f: impl FnOnce() -> T) -> T {
+    let _outside = Isolated::with(None);
+    f()
+}
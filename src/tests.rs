@@ -1,16 +1,13 @@
-#[cfg(test)]
-mod tests {
-    use crate::env::Env;
-    use crate::eval::eval;
-    use crate::exp::*;
-    use crate::parser::parse_tokens;
-    use crate::tokenizer::tokenize;
+use crate::eval::eval;
+use crate::eval_str;
+use crate::exp::*;
+use crate::interpreter::Interpreter;
+use crate::parser::parse_tokens;
+use crate::tokenizer::tokenize;
 
-    use crate::*;
-
-    #[test]
-    fn test_fib() {
-        let program = "(progn
+#[test]
+fn test_fib() {
+    let program = "(progn
 
    (defun fibonacci (N)
        (if (or (= N 0) (= N 1))
@@ -21,154 +18,1790 @@ mod tests {
 
    )";
 
-        let tokens = tokenize(program.into());
-        let mut iter = tokens.iter().peekable();
-        let tree = parse_tokens(&mut iter).unwrap();
-        
-        let mut env = Arc::new(Env::new());
-        let res = eval(&tree, &mut env).unwrap();
-
-        if let Num(res) = res {
-            assert_eq!(res, 8);
-        } else {
-            panic!("Unexpected result.");
-        }
+    let tokens = tokenize(program.into());
+    let mut iter = tokens.iter().peekable();
+    let tree = parse_tokens(&mut iter).unwrap();
+    
+    let env = Interpreter::new().env();
+    let res = eval(&tree, &env).unwrap();
+
+    if let Num(res) = res {
+        assert_eq!(res, 8);
+    } else {
+        panic!("Unexpected result.");
     }
+}
 
-    #[test]
-    fn test_deflambda() {
-        let program = "(progn
+#[test]
+fn test_deflambda() {
+    let program = "(progn
    (def identity (lambda (t) t))
    )";
 
-        let tokens = tokenize(program.into());
-        let mut iter = tokens.iter().peekable();
-        let tree = parse_tokens(&mut iter).unwrap();
+    let tokens = tokenize(program.into());
+    let mut iter = tokens.iter().peekable();
+    let tree = parse_tokens(&mut iter).unwrap();
 
-        let mut env = Arc::new(Env::new());
-        let res = eval(&tree, &mut env).unwrap();
+    let env = Interpreter::new().env();
+    let res = eval(&tree, &env).unwrap();
 
-        if let Vector(nil) = res {
-            assert!(nil.is_empty());
-        } else {
-            panic!("Unexpected result.");
-        }
+    if let Vector(nil) = res {
+        assert!(nil.is_empty());
+    } else {
+        panic!("Unexpected result.");
     }
+}
 
-    #[test]
-    fn test_let_no_bindings() {
-        let program = "(progn
+#[test]
+fn test_let_no_bindings() {
+    let program = "(progn
          (let ()
             2)
          )";
 
-        let tokens = tokenize(program.into());
-        let mut iter = tokens.iter().peekable();
-        let tree = parse_tokens(&mut iter).unwrap();
-        
-        let mut env = Arc::new(Env::new());
-        let res = eval(&tree, &mut env).unwrap();
-
-        if let Num(two) = res {
-            assert_eq!(two, 2);
-        } else {
-            panic!("Unexpected result.");
-        }
+    let tokens = tokenize(program.into());
+    let mut iter = tokens.iter().peekable();
+    let tree = parse_tokens(&mut iter).unwrap();
+    
+    let env = Interpreter::new().env();
+    let res = eval(&tree, &env).unwrap();
+
+    if let Num(two) = res {
+        assert_eq!(two, 2);
+    } else {
+        panic!("Unexpected result.");
     }
+}
 
-    #[test]
-    fn test_let_bindings() {
-        let program = "(let ((a 10) (b 100))
+#[test]
+fn test_let_bindings() {
+    let program = "(let ((a 10) (b 100))
                                 (+ a b))";
 
-        let tokens = tokenize(program.into());
-        let mut iter = tokens.iter().peekable();
-        let tree = parse_tokens(&mut iter).unwrap();
-       
-        let mut env = Arc::new(Env::new());
-        let res = eval(&tree, &mut env).unwrap();
-
-        if let Num(num) = res {
-            assert_eq!(num, 110);
-        } else {
-            panic!("Unexpected result.");
+    let tokens = tokenize(program.into());
+    let mut iter = tokens.iter().peekable();
+    let tree = parse_tokens(&mut iter).unwrap();
+   
+    let env = Interpreter::new().env();
+    let res = eval(&tree, &env).unwrap();
+
+    if let Num(num) = res {
+        assert_eq!(num, 110);
+    } else {
+        panic!("Unexpected result.");
+    }
+}
+
+#[test]
+fn test_nil() {
+    let program = "(print nil)";
+
+    let tokens = tokenize(program.into());
+    let mut iter = tokens.iter().peekable();
+    let tree = parse_tokens(&mut iter).unwrap();
+ 
+    let env = Interpreter::new().env();
+    let res = eval(&tree, &env).unwrap();
+    println!("{res:?}");
+}
+
+#[test]
+fn test_cons() {
+    let program = "(cons 1 2)";
+
+    let tokens = tokenize(program.into());
+    let mut iter = tokens.iter().peekable();
+    let tree = parse_tokens(&mut iter).unwrap();
+
+    let env = Interpreter::new().env();
+    let res = eval(&tree, &env).unwrap();
+    assert_eq!(format!("{res}"), "(1 PRINTING CONS IS UNIMPLEMENTED)");
+}
+
+#[test]
+fn test_list() {
+    let program = "(list 1 2 3 4 5 6)";
+
+    let tokens = tokenize(program.into());
+    let mut iter = tokens.iter().peekable();
+    let tree = parse_tokens(&mut iter).unwrap();
+   
+    let env = Interpreter::new().env();
+    let res = eval(&tree, &env).unwrap();
+    assert_eq!(format!("{res}"), "(1 2 3 4 5 6 )");
+}
+
+#[test]
+fn test_lists_bindings() {
+    let program = "(let ((a 10) (b 100))
+                                (+ a b))";
+
+    let tokens = tokenize(program.into());
+    let mut iter = tokens.iter().peekable();
+    let tree = parse_tokens(&mut iter).unwrap();
+  
+    let env = Interpreter::new().env();
+    let res = eval(&tree, &env).unwrap();
+
+    if let Num(num) = res {
+        assert_eq!(num, 110);
+    } else {
+        panic!("Unexpected result.");
+    }
+}
+
+#[test]
+fn test_strings() {
+    let program = "\"hello\"";
+
+    let tokens = tokenize(program.into());
+    let mut iter = tokens.iter().peekable();
+    let tree = parse_tokens(&mut iter).unwrap();
+  
+    let env = Interpreter::new().env();
+    let res = eval(&tree, &env).unwrap();
+
+    if let Str(hello) = res {
+        assert_eq!(hello, "hello");
+    } else {
+        panic!("Unexpected result.");
+    }
+}
+
+#[test]
+fn test_string_escapes() {
+    let res = eval_str(r#"(list "\"" "a  \"(b)\"" "" (string-ref "\"x" 1))"#).unwrap();
+    assert_eq!(format!("{res}"), "(\" a  \"(b)\"  #\\x )");
+
+    let err = eval_str("(print \"abc)").unwrap_err();
+    assert_eq!(err.to_string(), "Unterminated string");
+    assert!(crate::parse("\"abc\\").is_err());
+}
+
+#[test]
+fn test_char_literals() {
+    let program = "(list #\\a #\\space #\\newline #\\x41 #\\( (char->integer #\\a) (integer->char 98))";
+
+    let tokens = tokenize(program.into());
+    let mut iter = tokens.iter().peekable();
+    let tree = parse_tokens(&mut iter).unwrap();
+
+    let env = Interpreter::new().env();
+    let res = eval(&tree, &env).unwrap();
+    assert_eq!(
+        format!("{res}"),
+        "(#\\a #\\space #\\newline #\\A #\\( 97 #\\b )"
+    );
+}
+
+#[test]
+fn test_string_chars() {
+    let program = "(progn
+   (defun upper-count (chars)
+       (if chars
+           (if (char-upper-case? (car chars))
+               (+ 1 (upper-count (cdr chars)))
+               (upper-count (cdr chars)))
+           0))
+   (list (string-ref \"hello world\" 4)
+         (upper-count (string->list \"Hello World\"))
+         (list->string (string->list \"a (b) c\"))
+         (char-alphabetic? #\\a)
+         (char-whitespace? #\\a)))";
+
+    let tokens = tokenize(program.into());
+    let mut iter = tokens.iter().peekable();
+    let tree = parse_tokens(&mut iter).unwrap();
+
+    let env = Interpreter::new().env();
+    let res = eval(&tree, &env).unwrap();
+    assert_eq!(format!("{res}"), "(#\\o 2 a (b) c Bool(true) Bool(false) )");
+}
+
+#[test]
+fn test_map_lookup() {
+    let res = eval_str(
+        "(let ((m {\"one\" 1 \"two\" (+ 1 1)}))
+           (list (get m \"two\") (get m \"three\" 0) (contains? m \"three\")))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(2 0 Bool(false) )");
+}
+
+#[test]
+fn test_map_updates_leave_the_original() {
+    let res = eval_str(
+        "(let ((m {\"one\" 1 \"two\" 2}))
+           (list (get (assoc m \"three\" 3) \"three\")
+                 (contains? (dissoc m \"one\") \"one\")
+                 (count (merge m {\"three\" 3 \"one\" 10}))
+                 (get (update m \"one\" + 100) \"one\")
+                 (get m \"one\")
+                 (count m)))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(3 Bool(false) 3 101 1 2 )");
+}
+
+#[test]
+fn test_set_literals() {
+    let res = eval_str("(let ((s #{1 2 3})) (list (contains? s 2) (count (disj s 2)) (count s)))")
+        .unwrap();
+    assert_eq!(format!("{res}"), "(Bool(true) 2 3 )");
+}
+
+#[test]
+fn test_map_equality() {
+    let res = eval_str("(equal? {1 2 3 4} (assoc {3 4} 1 2))").unwrap();
+    assert_eq!(format!("{res}"), "Bool(true)");
+}
+
+#[test]
+fn test_printing_maps_and_sets() {
+    let res = eval_str("{\"ada\" 36}").unwrap();
+    assert_eq!(format!("{res}"), "{\"ada\" 36}");
+    let res = eval_str("#{#\\a}").unwrap();
+    assert_eq!(format!("{res}"), "#{#\\a}");
+    let res = eval_str("#{\"two words\"}").unwrap();
+    assert_eq!(format!("{res}"), "#{\"two words\"}");
+}
+
+#[test]
+fn test_keyword_names_are_not_empty() {
+    let err = eval_str("(keyword \"\")").unwrap_err();
+    assert_eq!(err.to_string(), "keyword: the name is empty");
+}
+
+#[test]
+fn test_hash_map_persistence() {
+    let mut versions = vec![Map::new()];
+    for i in 0..2000 {
+        let last = versions.last().unwrap();
+        versions.push(last.insert(Num(i), Num(i * i)));
+    }
+    let full = versions.last().unwrap();
+    assert_eq!(full.len(), 2000);
+    assert_eq!(versions[1000].len(), 1000);
+    assert_eq!(versions[1000].get(&Num(999)), Some(&Num(999 * 999)));
+    assert_eq!(versions[1000].get(&Num(1000)), None);
+    assert_eq!(full.iter().count(), 2000);
+
+    let mut shrunk = full.clone();
+    for i in (0..2000).step_by(2) {
+        shrunk = shrunk.remove(&Num(i));
+    }
+    assert_eq!(shrunk.len(), 1000);
+    assert_eq!(full.len(), 2000);
+    assert!(!shrunk.contains_key(&Num(10)));
+    assert_eq!(shrunk.get(&Num(11)), Some(&Num(121)));
+    assert!(shrunk != *full);
+    let rebuilt: Map = (0..2000).rev().map(|i| (Num(i), Num(i * i))).collect();
+    assert!(rebuilt == *full);
+}
+
+#[test]
+fn test_keywords_look_themselves_up() {
+    let res = eval_str(
+        "(def person {:name \"ada\" :age 36})
+         (list :name (:name person) (get person :age) (:missing person 0)
+               (equal? :a (keyword \"a\")))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(:name ada 36 0 Bool(true) )");
+}
+
+#[test]
+fn test_key_params() {
+    let greet = "(defun greet (name &key (greeting \"hello\") punctuation)
+                   (list greeting name punctuation))";
+    let res = eval_str(&format!(
+        "{greet} (list (greet \"bob\") (greet \"bob\" :punctuation #\\! :greeting \"hi\"))"
+    ))
+    .unwrap();
+    assert_eq!(format!("{res}"), "((hello bob () ) (hi bob #\\! ) )");
+    let err = eval_str(&format!("{greet} (greet \"bob\" :volume 11)")).unwrap_err();
+    assert!(err.to_string().contains(":volume"), "{err}");
+}
+
+#[test]
+fn test_key_defaults_see_earlier_params() {
+    let interpreter = Interpreter::new();
+    interpreter
+        .eval_str("(defun f (a &key (b a) (c (+ a b))) (list a b c))")
+        .unwrap();
+    let res = interpreter.eval_str("(list (f 1) (f 1 :b 5) (f 1 :c 0))").unwrap();
+    assert_eq!(format!("{res}"), "((1 1 2 ) (1 5 6 ) (1 1 0 ) )");
+}
+
+#[test]
+fn test_symbols_are_interned() {
+    assert_eq!(Sym::intern("fibonacci"), Sym::intern("fibonacci"));
+    assert_ne!(Sym::intern("fibonacci"), Sym::intern("fibonacci-with-let"));
+    assert_eq!(&*Sym::intern("fibonacci").name(), "fibonacci");
+}
+
+#[test]
+fn test_gensym() {
+    let res = eval_str("(list (gensym) (gensym \"tmp\"))").unwrap();
+    let List(Some(cons)) = res else {
+        panic!("Unexpected result.");
+    };
+    let Symbol(first) = cons.car else {
+        panic!("Unexpected result.");
+    };
+    assert!(first.name().starts_with("G__"));
+    // A gensym never collides with a symbol read from source.
+    assert_ne!(first, Sym::intern(&first.name()));
+}
+
+#[test]
+fn test_functions_see_caller_variables() {
+    // Like the interpreter always did, functions see the caller's variables.
+    let res = eval_str(
+        "(defun reads-n () n)
+         (defun binds-n (n) (reads-n))
+         (def g 1)
+         (defun reads-g () g)
+         (defun shadows-g (g) (reads-g))
+         (list (binds-n 42) (shadows-g 5) (reads-g))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(42 5 1 )");
+}
+
+#[test]
+fn test_let_shadowing() {
+    let res = eval_str(
+        "(defun shadowing (a b)
+           (let ((a (+ a 1)) (c 10))
+             (let ((b (+ a b c)))
+               (list a b c))))
+         (list ((lambda (x) (+ x 1)) 1) (shadowing 1 2))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(2 (2 14 10 ) )");
+}
+
+#[test]
+fn test_globals_defined_later() {
+    let res = eval_str(
+        "(defun uses-later-global () later-global)
+         (def later-global 42)
+         (uses-later-global)",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "42");
+}
+
+#[test]
+fn test_free_globals_skip_the_frames() {
+    // A global no frame has bound is read from its cell directly. Once a
+    // frame binds the name, callers see that binding again.
+    let res = eval_str(
+        "(def only-global 1)
+         (defun reads-only-global (n) (+ n only-global))
+         (reads-only-global 2)",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "3");
+    assert!(!Sym::intern("only-global").may_be_bound_locally());
+    assert!(Sym::intern("n").may_be_bound_locally());
+    let res = eval_str(
+        "(def shadowed-later 1)
+         (defun reads-shadowed-later () shadowed-later)
+         (list (reads-shadowed-later)
+               (let ((shadowed-later 2)) (reads-shadowed-later)))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(1 2 )");
+    assert!(Sym::intern("shadowed-later").may_be_bound_locally());
+}
+
+#[test]
+fn test_free_symbols_are_not_interned_as_globals() {
+    let interpreter = Interpreter::new();
+    let err = interpreter
+        .eval_str("(defun reads-unbound (x) (+ x not-defined-anywhere)) (reads-unbound 1)")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Symbol not-defined-anywhere is unbound");
+    assert!(interpreter
+        .find_cell(Sym::intern("not-defined-anywhere"))
+        .is_none());
+}
+
+#[test]
+fn test_interpreters_are_independent() {
+    let first = Interpreter::new();
+    let second = Interpreter::new();
+    first
+        .eval_str("(progn (def shared 1) (defun get-shared () shared))")
+        .unwrap();
+    second.eval_str("(def shared 2)").unwrap();
+    assert!(matches!(first.eval_str("(get-shared)"), Ok(Num(1))));
+    assert!(matches!(second.eval_str("shared"), Ok(Num(2))));
+    assert!(second.eval_str("(get-shared)").is_err());
+}
+
+#[test]
+fn test_forks_diverge() {
+    // A fork starts with the definitions of its parent and then diverges,
+    // even for functions defined before the fork.
+    let first = Interpreter::new();
+    first
+        .eval_str("(progn (def shared 1) (defun get-shared () shared))")
+        .unwrap();
+    let fork = first.fork();
+    fork.eval_str("(def shared 3)").unwrap();
+    assert!(matches!(fork.eval_str("(get-shared)"), Ok(Num(3))));
+    assert!(matches!(first.eval_str("(get-shared)"), Ok(Num(1))));
+}
+
+#[test]
+fn test_clones_share_globals() {
+    let first = Interpreter::new();
+    first
+        .eval_str("(progn (def shared 1) (defun get-shared () shared))")
+        .unwrap();
+    first.clone().eval_str("(def shared 4)").unwrap();
+    assert!(matches!(first.eval_str("(get-shared)"), Ok(Num(4))));
+}
+
+#[test]
+fn test_register_rust_closures() {
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::sync::Arc;
+
+    let interpreter = Interpreter::new();
+    let counter = Arc::new(AtomicI64::new(0));
+    let shared = counter.clone();
+    interpreter.register_fn("count!", move |by: i64| {
+        shared.fetch_add(by, Ordering::SeqCst) + by
+    });
+    let res = interpreter
+        .eval_str("(list (count! 2) (count! 3))")
+        .unwrap();
+    assert_eq!(format!("{res}"), "(2 5 )");
+    assert_eq!(counter.load(Ordering::SeqCst), 5);
+}
+
+#[test]
+fn test_registered_argument_conversions() {
+    let interpreter = Interpreter::new();
+    interpreter.register_fn("add", |a: i64, b: i64| a + b);
+    interpreter.register_fn("sum", |xs: Vec<i64>| xs.iter().sum::<i64>());
+    interpreter.register_fn("describe", |name: String, age: Option<i64>| {
+        (name, age.map(|age| age + 1))
+    });
+    interpreter.register_native("argc", |args, _| Ok(Num(args.len() as i64)));
+    let res = interpreter
+        .eval_str(
+            "(list (add 1 2) (sum (list 1 2 3))
+                   (describe \"ada\" 36) (describe \"bob\" nil) (argc 1 2 3 4))",
+        )
+        .unwrap();
+    assert_eq!(format!("{res}"), "(3 6 (ada 37 ) (bob () ) 4 )");
+}
+
+#[test]
+fn test_registered_function_errors() {
+    let interpreter = Interpreter::new();
+    interpreter.register_fn("add", |a: i64, b: i64| a + b);
+    interpreter.register_fn("checked-div", |a: i64, b: i64| -> Result<i64, LispErr> {
+        a.checked_div(b).ok_or_else(|| "division by zero".into())
+    });
+    let arity = interpreter.eval_str("(add 1)").unwrap_err();
+    assert_eq!(arity.to_string(), "add expects 2 arguments, got 1");
+    let types = interpreter.eval_str("(add 1 \"two\")").unwrap_err();
+    assert_eq!(
+        types.to_string(),
+        "add argument 2: expected integer, got string two"
+    );
+    let failed = interpreter.eval_str("(checked-div 1 0)").unwrap_err();
+    assert_eq!(failed.to_string(), "division by zero");
+}
+
+#[test]
+fn test_registered_functions_are_values() {
+    let interpreter = Interpreter::new();
+    interpreter.register_fn("add", |a: i64, b: i64| a + b);
+    let res = interpreter.eval_str("(update {:n 1} :n add 10)").unwrap();
+    assert_eq!(format!("{res}"), "{:n 11}");
+}
+
+struct Document {
+    title: String,
+    words: Vec<String>,
+}
+
+#[test]
+fn test_host_objects() {
+    use std::sync::Arc;
+
+    let interpreter = Interpreter::new();
+    interpreter.register_fn("open-document", |title: String| {
+        Object::new(Document {
+            words: title.split('-').map(String::from).collect(),
+            title,
+        })
+    });
+    interpreter.register_method::<Document, _>("title", |doc: Arc<Document>| doc.title.clone());
+    interpreter.register_method::<Document, _>("word", |doc: Arc<Document>, i: i64| {
+        doc.words.get(i as usize).cloned()
+    });
+
+    let res = interpreter
+        .eval_str(
+            "(let ((doc (open-document \"hello-big-world\"))
+                   (other (open-document \"hello-big-world\")))
+               (list (invoke doc :title)
+                     (invoke doc :word 1)
+                     (invoke doc :word 10)
+                     (type-of doc)
+                     (object-methods doc)
+                     (equal? doc doc)
+                     (equal? doc other)))",
+        )
+        .unwrap();
+    assert_eq!(
+        format!("{res}"),
+        "(hello-big-world big () Document (:title :word ) Bool(true) Bool(false) )"
+    );
+}
+
+#[test]
+fn test_host_objects_downcast() {
+    let interpreter = Interpreter::new();
+    interpreter.register_fn("open-document", |title: String| {
+        Object::new(Document {
+            words: title.split('-').map(String::from).collect(),
+            title,
+        })
+    });
+
+    let doc = interpreter.eval_str("(open-document \"a-b\")").unwrap();
+    let Object(object) = &doc else {
+        panic!("Unexpected result.");
+    };
+    assert!(format!("{doc}").starts_with("#<Document 0x"));
+    assert_eq!(object.downcast_ref::<Document>().unwrap().words.len(), 2);
+    assert!(object.downcast::<String>().is_none());
+
+    let err = interpreter
+        .eval_str("(invoke (open-document \"x\") :close)")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Document has no method close");
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone)]
+enum Shape {
+    Point,
+    Circle(i64),
+    Rect { w: i64, h: i64 },
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone)]
+struct Scene {
+    name: String,
+    shapes: Vec<Shape>,
+    origin: (i64, i64),
+    parent: Option<String>,
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip() {
+    use crate::serde_exp::{from_exp, to_exp};
+
+    let scene = Scene {
+        name: "demo".into(),
+        shapes: vec![Shape::Point, Shape::Circle(3), Shape::Rect { w: 2, h: 4 }],
+        origin: (0, 1),
+        parent: None,
+    };
+    let exp = to_exp(&scene).unwrap();
+    let Map(map) = &exp else {
+        panic!("Unexpected result.");
+    };
+    assert_eq!(
+        map.get(&Keyword(Keyword::intern("name"))),
+        Some(&Str("demo".into()))
+    );
+    assert_eq!(
+        map.get(&Keyword(Keyword::intern("parent"))),
+        Some(&List(None))
+    );
+    assert_eq!(from_exp::<Scene>(&exp).unwrap(), scene);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_from_lisp_values() {
+    use crate::serde_exp::from_exp;
+
+    // Values written by hand in lisp deserialize the same way, vectors
+    // included.
+    let res = eval_str(
+        "{:name \"lisp\"
+          :shapes (list :Point {:Circle 5} {:Rect {:w 1 :h 2}})
+          :origin (list 3 4)
+          :parent \"demo\"}",
+    )
+    .unwrap();
+    let from_lisp: Scene = from_exp(&res).unwrap();
+    assert_eq!(from_lisp.shapes[1], Shape::Circle(5));
+    assert_eq!(from_lisp.shapes[2], Shape::Rect { w: 1, h: 2 });
+    assert_eq!(from_lisp.origin, (3, 4));
+    assert_eq!(from_lisp.parent.as_deref(), Some("demo"));
+    assert_eq!(
+        from_exp::<Vec<i64>>(&Vector(vec![Num(1), Num(2)].into())).unwrap(),
+        vec![1, 2]
+    );
+
+    let err = from_exp::<Scene>(&Num(1)).unwrap_err();
+    assert!(err.to_string().contains("expected"), "{err}");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_registered_functions() {
+    use crate::serde_exp::{from_exp, Serde};
+
+    let interpreter = Interpreter::new();
+    interpreter.register_fn("grow", |Serde(shape): Serde<Shape>| {
+        Serde(match shape {
+            Shape::Circle(r) => Shape::Circle(r * 2),
+            other => other,
+        })
+    });
+    let res = interpreter.eval_str("(grow {:Circle 4})").unwrap();
+    assert_eq!(from_exp::<Shape>(&res).unwrap(), Shape::Circle(8));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_failures_are_errors() {
+    use crate::serde_exp::{to_exp, Serde};
+
+    let interpreter = Interpreter::new();
+    interpreter.register_fn("reserialize", |exp: Exp| Serde(exp));
+    let res = interpreter.eval_str("(reserialize (list 1 2))").unwrap();
+    assert_eq!(format!("{res}"), "(1 2 )");
+    let err = interpreter.eval_str("(reserialize car)").unwrap_err();
+    assert!(err.to_string().contains("serialize"), "{err}");
+
+    // Keyword keys come back as strings.
+    let map = interpreter.eval_str("{:a 1}").unwrap();
+    let res = to_exp(&map).unwrap();
+    assert_eq!(res, interpreter.eval_str("{\"a\" 1}").unwrap());
+}
+
+#[test]
+fn test_eval_str() {
+    let interpreter = Interpreter::new();
+    let res = interpreter
+        .eval_str("(def x 10) (defun add-x (n) (+ n x)) (add-x 5)")
+        .unwrap();
+    assert!(matches!(res, Num(15)));
+    assert!(matches!(interpreter.eval_str("").unwrap(), List(None)));
+
+    let err = crate::eval_str("(+ 1 (* 2 3)").unwrap_err();
+    assert_eq!(err.to_string(), "Unexpected EOF while parsing");
+}
+
+#[test]
+fn test_error_kinds() {
+    use crate::error::ErrorKind;
+
+    let interpreter = Interpreter::new();
+    let err = interpreter.eval_str("(+ 1 undefined-thing)").unwrap_err();
+    assert!(
+        matches!(err.kind(), ErrorKind::UnboundSymbol(sym) if &*sym.name() == "undefined-thing")
+    );
+
+    let err = interpreter.eval_str("(car 5)").unwrap_err();
+    let ErrorKind::Type { expected, got } = err.kind() else {
+        panic!("Unexpected error {err:?}");
+    };
+    assert_eq!((expected.as_str(), got), ("list", &Num(5)));
+    assert_eq!(err.to_string(), "car: expected list, got integer 5");
+
+    let err = interpreter
+        .eval_str("(defun first-of (x) (car x)) (first-of)")
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Arity { got: 0, .. }));
+}
+
+#[test]
+fn test_error_spans_and_backtraces() {
+    use crate::error::Span;
+
+    let err = crate::eval_str(
+        "(defun first-of (x) (car x))\n(defun outer (x)\n  (first-of x))\n(outer 5)",
+    )
+    .unwrap_err();
+    // The span is the innermost form, the backtrace goes outwards.
+    assert_eq!(
+        err.span(),
+        Some(Span {
+            line: 1,
+            column: 21
+        })
+    );
+    let frames: Vec<_> = err
+        .backtrace()
+        .iter()
+        .map(|frame| frame.name.as_str())
+        .collect();
+    assert_eq!(frames, ["car", "first-of", "outer"]);
+    assert_eq!(err.backtrace()[1].span, Some(Span { line: 3, column: 3 }));
+    assert!(err.report().contains("in (outer 5) at 4:1"), "{}", err.report());
+}
+
+#[test]
+fn test_parse_error_spans() {
+    use crate::error::{ErrorKind, Span};
+
+    let err = crate::parse("(list 1\n  2))").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Parse(_)));
+    assert_eq!(err.span(), Some(Span { line: 2, column: 5 }));
+}
+
+#[test]
+fn test_errors_cross_threads() {
+    use crate::error::LispError;
+
+    fn is_send_sync<T: Send + Sync>() {}
+    is_send_sync::<LispError>();
+
+    let handle = std::thread::spawn(|| crate::eval_str("(car 1 2)").unwrap_err());
+    let err = handle.join().unwrap();
+    assert_eq!(err.to_string(), "car expects 1 argument, got 2");
+}
+
+#[test]
+fn test_lambda_arity_errors_name_the_function() {
+    let interpreter = Interpreter::new();
+    interpreter
+        .eval_str("(defun pair (a b) (list a b)) (defun opts (a b &key c) a)")
+        .unwrap();
+    let err = interpreter.eval_str("(pair 1)").unwrap_err();
+    assert_eq!(err.to_string(), "pair expects 2 arguments, got 1");
+    let err = interpreter.eval_str("(opts)").unwrap_err();
+    assert_eq!(err.to_string(), "opts expects at least 2 arguments, got 0");
+    let err = interpreter.eval_str("((lambda (x) x))").unwrap_err();
+    assert_eq!(err.to_string(), "function expects 1 argument, got 0");
+}
+
+#[test]
+fn test_catch_by_kind() {
+    let res = eval_str(
+        "(try (car 5)
+           (catch :arity-error e \"never\")
+           (catch :type-error e (list \"not a list\" (:message e))))",
+    )
+    .unwrap();
+    assert_eq!(
+        format!("{res}"),
+        "(not a list car: expected list, got integer 5 )"
+    );
+}
+
+#[test]
+fn test_finally_runs_once() {
+    let res = eval_str(
+        "(def cleanups 0)
+         (defun safe-car (x)
+           (try (car x)
+             (catch :type-error e nil)
+             (finally (def cleanups (+ cleanups 1)))))
+         (list (safe-car (list 1 2)) (safe-car 5) cleanups)",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(1 () 2 )");
+}
+
+#[test]
+fn test_raised_values() {
+    // Raised values come back as they were, kinds can be picked with :kind.
+    let res = eval_str(
+        "(try (raise {:kind :not-found :key 3})
+           (catch :type-error e 1)
+           (catch :not-found e (:key e)))",
+    )
+    .unwrap();
+    assert!(matches!(res, Num(3)));
+    let res = eval_str("(try (error \"boom\") (catch :error e e))").unwrap();
+    assert!(matches!(res, Str(ref s) if s == "boom"));
+}
+
+#[test]
+fn test_uncaught_errors_run_finally() {
+    use crate::error::ErrorKind;
+
+    // Uncaught errors go through finally and keep going up.
+    let interpreter = Interpreter::new();
+    let err = interpreter
+        .eval_str("(try (error 42) (catch :type-error e 0) (finally (def cleanups 10)))")
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::User(Num(42))));
+    assert!(matches!(interpreter.eval_str("cleanups").unwrap(), Num(10)));
+}
+
+#[test]
+fn test_assert() {
+    let interpreter = Interpreter::new();
+    let err = interpreter
+        .eval_str("(assert (= 1 1) (= (+ 1 1) 3))")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Assertion failed: (= (+ 1 1) 3)");
+    let res = interpreter
+        .eval_str("(try (assert false) (catch :assertion-error e (:message e)))")
+        .unwrap();
+    assert!(matches!(res, Str(ref s) if s == "Assertion failed: false"));
+}
+
+#[test]
+fn test_try_returns_values() {
+    // Special forms hand back values, which must not be evaluated again: a
+    // symbol that came out of gensym would be looked up.
+    for src in ["(try (gensym))", "(try (car 1) (catch e (gensym)))"] {
+        let res = eval_str(src);
+        assert!(matches!(res, Ok(Symbol(_))), "{src}: {res:?}");
+    }
+}
+
+#[test]
+fn test_handler_bind_and_restart_case_return_values() {
+    for src in [
+        "(handler-bind ((:type-error (lambda (c) 1))) (gensym))",
+        "(restart-case (gensym) (:use-value (v) v))",
+        "(restart-case (invoke-restart :use-value (gensym)) (:use-value (v) v))",
+    ] {
+        let res = eval_str(src);
+        assert!(matches!(res, Ok(Symbol(_))), "{src}: {res:?}");
+    }
+}
+
+#[test]
+fn test_let_and_lambda_return_values() {
+    for src in [
+        "(let () (gensym))",
+        "(let ((s (gensym))) s)",
+        "((lambda () (gensym)))",
+    ] {
+        let res = eval_str(src);
+        assert!(matches!(res, Ok(Symbol(_))), "{src}: {res:?}");
+    }
+}
+
+#[test]
+fn test_handlers_invoke_restarts() {
+    let interpreter = Interpreter::new();
+    interpreter
+        .eval_str(
+            "(defun parse-record (r)
+               (restart-case (if (= r 0) (error {:kind :bad-record :record r}) r)
+                 (:skip () :skipped)
+                 (:use-value (v) v)))
+             (defun parse-all (rs)
+               (if rs (cons (parse-record (car rs)) (parse-all (cdr rs))) nil))",
+        )
+        .unwrap();
+
+    // Handlers run before unwinding and pick a restart.
+    let res = interpreter
+        .eval_str(
+            "(handler-bind ((:bad-record (lambda (c) (invoke-restart :use-value (+ 100 (:record c))))))
+               (parse-all (list 1 0 3)))",
+        )
+        .unwrap();
+    assert_eq!(format!("{res}"), "(1 100 3 )");
+    let res = interpreter
+        .eval_str(
+            "(handler-bind ((:bad-record (lambda (c) (invoke-restart :skip))))
+               (parse-all (list 0 2)))",
+        )
+        .unwrap();
+    assert_eq!(format!("{res}"), "(:skipped 2 )");
+}
+
+#[test]
+fn test_handlers_decline_by_returning() {
+    // Returning declines, and the error goes on to the next handler.
+    let res = eval_str(
+        "(def seen 0)
+         (list
+           (try (handler-bind ((:error (lambda (c) (def seen (+ seen 1)))))
+                  (car 5))
+                (catch :type-error e (:kind e)))
+           (handler-bind ((:error (lambda (c) (def seen (+ seen 10)))))
+             (try (car 5) (catch e :caught)))
+           seen)",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(:type-error :caught 1 )");
+}
+
+#[test]
+fn test_signal_and_compute_restarts() {
+    let res = eval_str(
+        "(list (signal {:kind :note})
+               (restart-case (compute-restarts) (:a () 1) (:b () 2)))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(() (:a :b ) )");
+    let err = eval_str("(invoke-restart :nowhere)").unwrap_err();
+    assert_eq!(err.to_string(), "No restart named :nowhere is active");
+}
+
+#[test]
+fn test_debugger() {
+    // The host decides what happens to errors nothing handles.
+    let interpreter = Interpreter::new();
+    interpreter.set_debugger(|_, err, restarts| {
+        assert_eq!(err.to_string(), "car: expected list, got integer 5");
+        let index = restarts.iter().position(|r| r.name.name() == "use-value")?;
+        Some((index, vec![Num(7)]))
+    });
+    let res = interpreter
+        .eval_str("(restart-case (car 5) (:abort () 0) (:use-value (v) v))")
+        .unwrap();
+    assert!(matches!(res, Num(7)));
+    assert!(interpreter.eval_str("(car 5)").is_err());
+}
+
+#[test]
+fn test_error_backtraces() {
+    let interpreter = Interpreter::new();
+    interpreter
+        .eval_str(
+            "(defun inner (s n) (car n))
+             (defun middle (n) (inner \"some text\" (+ n 1)))",
+        )
+        .unwrap();
+
+    let err = interpreter.eval_str("(middle 41)").unwrap_err();
+    let frames: Vec<String> = err.backtrace().iter().map(|f| f.to_string()).collect();
+    assert_eq!(
+        frames,
+        [
+            "(car 42) at 1:20",
+            "(inner \"some text\" 42) at 2:32",
+            "(middle 41) at 1:1"
+        ]
+    );
+
+    // Long arguments are cut.
+    let err = interpreter
+        .eval_str("(inner 0 \"a string that is much longer than forty characters\")")
+        .unwrap_err();
+    assert!(err.backtrace()[1].args.ends_with("than forty..."));
+}
+
+#[test]
+fn test_backtrace_builtin() {
+    let interpreter = Interpreter::new();
+    let res = interpreter
+        .eval_str(
+            "(defun show-stack (x) (backtrace))
+             (defun outer-stack () (show-stack (+ 1 2)))
+             (let ((frames (outer-stack)))
+               (list (:name (car frames)) (:args (car frames)) (:column (car frames))
+                     (:name (car (cdr frames))) (cdr (cdr frames))))",
+        )
+        .unwrap();
+    assert_eq!(format!("{res}"), "(show-stack (+ 1 2) 36 outer-stack () )");
+    assert!(matches!(
+        interpreter.eval_str("(backtrace)").unwrap(),
+        List(None)
+    ));
+}
+
+#[test]
+fn test_builtin_arity() {
+    use crate::env::init_toplevel;
+    use crate::error::ErrorKind;
+
+    // Builtins that are fine with no arguments, and ones that take any
+    // number of arguments past the first few.
+    let no_args = [
+        "+", "*", "print", "progn", "list", "hash-map", "hash-set", "merge", "try",
+        "compute-restarts", "gensym", "backtrace", "chan", "promise",
+        "dosync",
+    ];
+    let variadic = [
+        "+", "*", "print", "progn", "list", "hash-map", "hash-set", "merge", "try",
+        "-", "=", "or", "equal?", "assert", "assoc", "dissoc", "conj", "disj", "update",
+        "invoke", "invoke-restart", "handler-bind", "restart-case", "let", "defun", "lambda",
+        "select", "swap!", "dosync", "alter", "commute", "binding", "parameterize",
+        "with-timeout", "sandbox",
+    ];
+    let interpreter = Interpreter::new();
+    let mut names: Vec<String> = init_toplevel()
+        .into_iter()
+        .filter(|(_, value)| matches!(value, Func(_) | Macro(_) | Special(_)))
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+    let mut bad = vec![];
+    for name in names {
+        let mut calls = vec![];
+        if !no_args.contains(&name.as_str()) {
+            calls.push(format!("({name})"));
+        }
+        if !variadic.contains(&name.as_str()) {
+            calls.push(format!("({name}{})", " nil".repeat(12)));
+        }
+        for src in calls {
+            match interpreter.eval_str(&src) {
+                Err(err) if matches!(err.kind(), ErrorKind::Arity { .. }) => {}
+                res => bad.push(format!("{src}: {res:?}")),
+            }
         }
     }
+    assert!(bad.is_empty(), "{}", bad.join("\n"));
+}
 
-    #[test]
-    fn test_nil() {
-        let program = "(print nil)";
+#[test]
+fn test_builtin_panics() {
+    use crate::error::ErrorKind;
 
-        let tokens = tokenize(program.into());
-        let mut iter = tokens.iter().peekable();
-        let tree = parse_tokens(&mut iter).unwrap();
-     
-        let mut env = Arc::new(Env::new());
-        let res = eval(&tree, &mut env).unwrap();
-        println!("{res:?}");
+    let interpreter = Interpreter::new();
+    interpreter.register_fn("explode", |n: i64| -> i64 { panic!("boom {n}") });
+    let err = interpreter.eval_str("(explode 3)").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Panic { .. }));
+    assert_eq!(err.to_string(), "explode panicked: boom 3");
+
+    // The panic is an error like any other, scripts can catch it and go on.
+    let res = interpreter
+        .eval_str("(try (explode 4) (catch :panic e (:message e)))")
+        .unwrap();
+    assert!(matches!(res, Str(ref s) if s == "explode panicked: boom 4"));
+    assert!(matches!(interpreter.eval_str("(+ 1 2)").unwrap(), Num(3)));
+
+    // Functions called by other builtins are named too.
+    let err = interpreter.eval_str("(update {:n 5} :n explode)").unwrap_err();
+    assert_eq!(err.to_string(), "explode panicked: boom 5");
+}
+
+#[test]
+fn test_integer_overflow() {
+    let interpreter = Interpreter::new();
+    for (src, message) in [
+        ("(+ 9223372036854775807 1)", "+: integer overflow"),
+        ("(- (- 0 9223372036854775807) 2)", "-: integer overflow"),
+        ("(* 4611686018427387904 2)", "*: integer overflow"),
+    ] {
+        let err = interpreter.eval_str(src).unwrap_err();
+        assert_eq!(err.to_string(), message);
     }
+    let res = interpreter.eval_str("(- 0 9223372036854775807 1)").unwrap();
+    assert_eq!(res, Num(i64::MIN));
+}
 
-    #[test]
-    fn test_cons() {
-        let program = "(cons 1 2)";
+#[test]
+fn test_thread_join() {
+    let res = eval_str(
+        "(def t (thread/spawn (lambda () (* 6 7))))
+         (list (thread/join t) (thread/join t) (type-of t))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(42 42 thread )");
+}
 
-        let tokens = tokenize(program.into());
-        let mut iter = tokens.iter().peekable();
-        let tree = parse_tokens(&mut iter).unwrap();
-    
-        let mut env = Arc::new(Env::new());
-        let res = eval(&tree, &mut env).unwrap();
-        assert_eq!(format!("{res}"), "(1 PRINTING CONS IS UNIMPLEMENTED)");
+#[test]
+fn test_thread_join_raises_errors_again() {
+    // Errors come out of join, where handlers can take them.
+    let res = eval_str(
+        "(def bad (thread/spawn (lambda () (raise :oops))))
+         (list (try (thread/join bad) (catch e e))
+               (try (thread/join bad) (catch e e)))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(:oops :oops )");
+}
+
+#[test]
+fn test_thread_join_all() {
+    let interpreter = Interpreter::new();
+    let res = interpreter
+        .eval_str(
+            "(defun work (n) (thread/spawn (lambda () (* n n))))
+             (thread/join-all (list (work 1) (work 2) (work 3)))",
+        )
+        .unwrap();
+    assert_eq!(format!("{res}"), "(1 4 9 )");
+    let err = interpreter
+        .eval_str("(thread/join-all (list (work 1) (thread/spawn (lambda () (car 2)))))")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "car: expected list, got integer 2");
+}
+
+#[test]
+fn test_host_joins_leftover_threads() {
+    // Threads nobody joined are waited for by the host.
+    let interpreter = Interpreter::new();
+    interpreter
+        .eval_str("(thread/spawn (lambda () (thread/spawn (lambda () (car 3)))))")
+        .unwrap();
+    let errors: Vec<String> = interpreter
+        .join_threads()
+        .iter()
+        .map(|e| e.to_string())
+        .collect();
+    assert_eq!(errors, ["car: expected list, got integer 3"]);
+    assert!(interpreter.join_threads().is_empty());
+}
+
+#[test]
+fn test_channels() {
+    let res = eval_str(
+        "(def ch (chan 2))
+         (defun produce (n) (if (= n 0) (close ch) (progn (send ch n) (produce (- n 1)))))
+         (defun consume (sum)
+           (let ((x (recv ch :done)))
+             (if (equal? x :done) sum (consume (+ sum x)))))
+         (thread/spawn (lambda () (produce 100)))
+         (consume 0)",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "5050");
+}
+
+#[test]
+fn test_receiving_without_waiting() {
+    let res =
+        eval_str("(def ch (chan)) (list (try-recv ch :empty) (recv-timeout ch 10 :late))").unwrap();
+    assert_eq!(format!("{res}"), "(:empty :late )");
+}
+
+#[test]
+fn test_closed_channels() {
+    let res = eval_str(
+        "(def ch (chan))
+         (list (progn (send ch 1) (close ch) (recv ch)) (recv ch)
+               (try (send ch 2) (catch e (:message e))))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(1 () send: the channel is closed )");
+}
+
+#[test]
+fn test_select() {
+    let res = eval_str(
+        "(def a (chan))
+         (def b (chan))
+         (thread/spawn (lambda () (send b 7)))
+         (list (select (a x (list :a x)) (b x (list :b x)))
+               (select (a x x) (:timeout 10 :timed-out)))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "((:b 7 ) :timed-out )");
+}
+
+#[test]
+fn test_select_returns_values() {
+    for src in [
+        "(let ((ch (chan 1))) (send ch 1) (select (ch v (gensym))))",
+        "(select ((chan) v 1) (:timeout 0 (gensym)))",
+    ] {
+        let res = eval_str(src);
+        assert!(matches!(res, Ok(Symbol(_))), "{src}: {res:?}");
     }
+}
+
+#[test]
+fn test_atoms() {
+    let res = eval_str(
+        "(def counter (atom 0))
+         (defun bump (n) (if (= n 0) nil (progn (swap! counter + 1) (bump (- n 1)))))
+         (defun spawn (n acc)
+           (if (= n 0) acc (spawn (- n 1) (cons (thread/spawn (lambda () (bump 100))) acc))))
+         (thread/join-all (spawn 8 nil))
+         @counter",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "800");
+}
 
-    #[test]
-    fn test_list() {
-        let program = "(list 1 2 3 4 5 6)";
+#[test]
+fn test_atom_updates() {
+    let res = eval_str(
+        "(def a (atom 1))
+         (list (compare-and-set! a 2 3) (compare-and-set! a 1 3) (deref a)
+               (reset! a 10) (swap! a - 4) @a)",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(Bool(false) Bool(true) 3 10 6 6 )");
+}
 
-        let tokens = tokenize(program.into());
-        let mut iter = tokens.iter().peekable();
-        let tree = parse_tokens(&mut iter).unwrap();
-   
-        let mut env = Arc::new(Env::new());
-        let res = eval(&tree, &mut env).unwrap();
-        assert_eq!(format!("{res}"), "(1 2 3 4 5 6 )");
+#[test]
+fn test_atom_validators() {
+    let interpreter = Interpreter::new();
+    let err = interpreter
+        .eval_str("(set-validator! (atom 1) keyword?)")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Validator rejected the value 1");
+    let res = interpreter
+        .eval_str(
+            "(def b (atom :a :validator keyword?))
+             (list (try (reset! b 5) (catch e (:message e))) @b)",
+        )
+        .unwrap();
+    assert_eq!(format!("{res}"), "(Validator rejected the value 5 :a )");
+}
+
+#[test]
+fn test_atom_watches() {
+    let res = eval_str(
+        "(def changes (atom nil))
+         (def b (atom :a))
+         (add-watch b :log (lambda (key ref old new)
+                              (swap! changes (lambda (l) (cons (list key old new) l)))))
+         (reset! b :b)
+         (swap! b (lambda (x) :c))
+         @changes",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "((:log :b :c ) (:log :a :b ) )");
+}
+
+#[test]
+fn test_pmap() {
+    let res = eval_str(
+        "(defun range (n acc) (if (= n 0) acc (range (- n 1) (cons n acc))))
+         (def squares (pmap (lambda (x) (* x x)) (range 200 nil)))
+         (list (car squares) (car (cdr squares)) (count squares))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(1 4 200 )");
+}
+
+#[test]
+fn test_nested_futures() {
+    // Futures waiting on futures don't run out of workers.
+    let res = eval_str(
+        "(defun pfib (n)
+           (if (or (= n 0) (= n 1))
+               1
+               (let ((a (future (lambda () (pfib (- n 1)))))
+                     (b (future (lambda () (pfib (- n 2))))))
+                 (+ @a @b))))
+         (pfib 15)",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "987");
+}
+
+#[test]
+fn test_promises() {
+    let res = eval_str(
+        "(def p (promise))
+         (def waiting (future (lambda () (+ 1 @p))))
+         (list (realized? p) (deref p 10 :timed-out)
+               (deliver p 41) (deliver p 0) @waiting (realized? waiting))",
+    )
+    .unwrap();
+    assert_eq!(
+        format!("{res}"),
+        "(Bool(false) :timed-out Bool(true) Bool(false) 42 Bool(true) )"
+    );
+}
+
+#[test]
+fn test_future_errors() {
+    let interpreter = Interpreter::new();
+    let res = interpreter
+        .eval_str("(try @(future (lambda () (raise :oops))) (catch e e))")
+        .unwrap();
+    assert_eq!(format!("{res}"), ":oops");
+    let err = interpreter
+        .eval_str("(pfor-each (lambda (x) (car x)) (list (list 1) 2))")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "car: expected list, got integer 2");
+}
+
+#[test]
+fn test_refs() {
+    // Transfers between two accounts never lose money, even with a try in
+    // the way of the retries.
+    let res = eval_str(
+        "(def a (ref 1000))
+         (def b (ref 0))
+         (def broken (atom 0))
+         (defun transfer (n)
+           (if (= n 0)
+               nil
+               (progn
+                 (dosync (try (progn (alter a - 1) (alter b + 1)) (catch e e)))
+                 (if (= (dosync (+ @a @b)) 1000) nil (swap! broken + 1))
+                 (transfer (- n 1)))))
+         (defun spawn (n acc)
+           (if (= n 0) acc (spawn (- n 1) (cons (thread/spawn (lambda () (transfer 50))) acc))))
+         (thread/join-all (spawn 8 nil))
+         (list @a @b @broken)",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(600 400 0 )");
+}
+
+#[test]
+fn test_commute() {
+    let res = eval_str(
+        "(def hits (ref 0))
+         (pfor-each (lambda (x) (dosync (commute hits + x))) (list 1 2 3 4 5 6 7 8 9 10))
+         @hits",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "55");
+}
+
+#[test]
+fn test_nested_dosync_joins_the_transaction() {
+    let res = eval_str(
+        "(def hits (ref 1))
+         (list (dosync (ref-set hits 0) (dosync (alter hits + 5)) @hits) @hits)",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(5 5 )");
+}
+
+#[test]
+fn test_ref_errors() {
+    let interpreter = Interpreter::new();
+    let err = interpreter.eval_str("(alter (ref 1) + 1)").unwrap_err();
+    assert_eq!(err.to_string(), "alter has to be called inside dosync");
+    let err = interpreter
+        .eval_str("(def r (ref 1)) (dosync (commute r + 1) (ref-set r 5))")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "ref-set: can't set a ref after commute");
+}
+
+#[test]
+fn test_dosync_returns_values() {
+    for src in [
+        "(dosync (gensym))",
+        "(let ((r (ref 0))) (dosync (alter r (lambda (v) (+ v 1))) (gensym)))",
+    ] {
+        let res = eval_str(src);
+        assert!(matches!(res, Ok(Symbol(_))), "{src}: {res:?}");
     }
+}
 
-    #[test]
-    fn test_lists_bindings() {
-        let program = "(let ((a 10) (b 100))
-                                (+ a b))";
+#[test]
+fn test_commute_with_nested_dosync() {
+    let interpreter = Interpreter::new();
+    let src = "(def r (ref 0))
+               (def s (ref 0))
+               (dosync (commute r (lambda (v) (dosync (alter s (lambda (x) (+ x 1)))) (+ v 1))))
+               @r";
+    assert!(matches!(interpreter.eval_str(src).unwrap(), Num(1)));
+    // The function ran once in the transaction and once on commit.
+    assert!(matches!(interpreter.eval_str("@s").unwrap(), Num(2)));
+}
 
-        let tokens = tokenize(program.into());
-        let mut iter = tokens.iter().peekable();
-        let tree = parse_tokens(&mut iter).unwrap();
-  
-        let mut env = Arc::new(Env::new());
-        let res = eval(&tree, &mut env).unwrap();
+#[test]
+fn test_binding_returns_values() {
+    for src in [
+        "(progn (defvar *x* 1) (binding ((*x* 2)) (gensym)))",
+        "(progn (defvar *x* 1) (parameterize ((*x* 2)) (gensym)))",
+    ] {
+        let res = eval_str(src);
+        assert!(matches!(res, Ok(Symbol(_))), "{src}: {res:?}");
+    }
+}
 
-        if let Num(num) = res {
-            assert_eq!(num, 110);
-        } else {
-            panic!("Unexpected result.");
+#[test]
+fn test_concurrent_globals() {
+    let interpreter = Interpreter::new();
+    interpreter.eval_str("(def shared (list 0 0))").unwrap();
+    // Writers redefine shared and add new globals while readers look them
+    // up. A reader sees one whole definition or another, never a missing one.
+    std::thread::scope(|scope| {
+        for writer in 0..4 {
+            let interpreter = &interpreter;
+            scope.spawn(move || {
+                for i in 0..200 {
+                    let src = format!(
+                        "(def shared (list {i} {i})) (defun f-{writer}-{i} () {i}) (f-{writer}-{i})"
+                    );
+                    assert_eq!(interpreter.eval_str(&src).unwrap(), Num(i));
+                }
+            });
+        }
+        for _ in 0..4 {
+            let interpreter = &interpreter;
+            scope.spawn(move || {
+                for _ in 0..200 {
+                    let res = interpreter
+                        .eval_str("(let ((s shared)) (= (car s) (car (cdr s))))")
+                        .unwrap();
+                    assert!(matches!(res, Bool(true)));
+                }
+            });
         }
+    });
+    assert_eq!(interpreter.eval_str("(+ (f-0-199) (f-3-7))").unwrap(), Num(206));
+}
+
+#[test]
+fn test_dynamic_variables() {
+    let res = eval_str(
+        "(defvar level :info)
+         (defvar level :debug)
+         (defparameter depth 0)
+         (defun show () (list level depth))
+         (list (show)
+               (binding ((level :warn) (depth (+ depth 1)))
+                 (list (show) (parameterize ((depth 5)) (show)) (show)))
+               (show))",
+    )
+    .unwrap();
+    assert_eq!(
+        format!("{res}"),
+        "((:info 0 ) ((:warn 1 ) (:warn 5 ) (:warn 1 ) ) (:info 0 ) )"
+    );
+}
+
+#[test]
+fn test_errors_unwind_bindings() {
+    let res = eval_str(
+        "(defvar level :info)
+         (try (binding ((level :error)) (car level)) (catch e level))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), ":info");
+}
+
+#[test]
+fn test_bindings_stay_in_their_thread() {
+    // Other threads only see the global value.
+    let res = eval_str(
+        "(defvar level :info)
+         (binding ((level :trace))
+           (list level (thread/join (thread/spawn (lambda () level)))
+                 @(future (lambda () level))))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(:trace :info :info )");
+}
+
+#[test]
+fn test_binding_needs_dynamic_variables() {
+    let err = Interpreter::new()
+        .eval_str("(def plain 1) (binding ((plain 2)) plain)")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "binding: plain isn't a dynamic variable");
+}
+
+#[test]
+fn test_def_inside_binding() {
+    // def sets the innermost binding of a dynamic variable, which goes away
+    // with it, and the global value when it isn't bound.
+    let res = eval_str(
+        "(defvar level :info)
+         (list (binding ((level :warn))
+                 (binding ((level :debug)) (def level :trace) level))
+               (binding ((level :warn))
+                 (binding ((level :debug)) (def level :trace))
+                 level)
+               level
+               (progn (def level :error) level))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(:trace :warn :info :error )");
+}
+
+#[test]
+fn test_with_timeout() {
+    let res = eval_str(
+        "(defun forever () (dotimes 1000000000000 (lambda (i) i)))
+         (list (try (with-timeout 20 (forever)) (catch :timeout e (:message e)))
+               (with-timeout 1000 (+ 1 2)))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(Timed out after 20ms 3 )");
+}
+
+#[test]
+fn test_catch_all_lets_timeouts_through() {
+    // Catches without a kind and :error catches leave the timeout to the
+    // code around them.
+    let res = eval_str(
+        "(defun forever () (dotimes 1000000000000 (lambda (i) i)))
+         (list (try (with-timeout 20 (try (forever) (catch e :caught)))
+                    (catch :timeout e :timed-out))
+               (try (with-timeout 20 (try (forever) (catch :error e :caught)))
+                    (catch :timeout e :timed-out)))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(:timed-out :timed-out )");
+}
+
+#[test]
+fn test_thread_cancel() {
+    // A cancelled thread stops at its next call, and so do its futures. Once
+    // cancelled, catching the error doesn't keep it going.
+    let res = eval_str(
+        "(defun forever () (dotimes 1000000000000 (lambda (i) i)))
+         (def t (thread/spawn (lambda ()
+                  (try (forever) (catch e @(future forever))))))
+         (thread/cancel t)
+         (try (thread/join t) (catch :cancelled e (:message e)))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "Evaluation cancelled");
+}
+
+#[test]
+fn test_spawned_work_outlives_with_timeout() {
+    // Only cancelling reaches threads and futures, the deadline stays with
+    // the with-timeout form.
+    let res = eval_str(
+        "(defun busy () (dotimes 300000 (lambda (x) x)) :finished)
+         (def t (with-timeout 20 (thread/spawn busy)))
+         (def f (with-timeout 20 (future busy)))
+         (list (thread/join t) @f)",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(:finished :finished )");
+}
+
+#[test]
+fn test_with_timeout_returns_values() {
+    let res = eval_str("(with-timeout 1000 (gensym))");
+    assert!(matches!(res, Ok(Symbol(_))), "{res:?}");
+}
+
+#[test]
+fn test_timeout_stops_blocking_waits() {
+    use crate::error::ErrorKind;
+
+    let interpreter = Interpreter::new();
+    interpreter
+        .eval_str(
+            "(def c (chan 1))
+             (def p (promise))
+             (def blocked (chan))
+             (def t (thread/spawn (lambda () (recv blocked))))",
+        )
+        .unwrap();
+    let waits = [
+        "(recv c)",
+        "(progn (send c 1) (send c 2))",
+        "(select (c v v))",
+        "@p",
+        "(thread/join t)",
+    ];
+    for wait in waits {
+        let err = interpreter
+            .eval_str(&format!("(with-timeout 50 {wait})"))
+            .unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Timeout(_)), "{wait}: {err}");
+        interpreter.eval_str("(try-recv c)").unwrap();
     }
+    interpreter.eval_str("(close blocked)").unwrap();
+}
 
-    #[test]
-    fn test_strings() {
-        let program = "\"hello\"";
+#[test]
+fn test_cancel_stops_blocked_thread() {
+    let interpreter = Interpreter::new();
+    let res = interpreter
+        .eval_str(
+            "(def t (thread/spawn (lambda () (recv (chan 1)))))
+             (thread/cancel t)
+             (try (thread/join t) (catch :cancelled e (:message e)))",
+        )
+        .unwrap();
+    assert_eq!(format!("{res}"), "Evaluation cancelled");
+}
 
-        let tokens = tokenize(program.into());
-        let mut iter = tokens.iter().peekable();
-        let tree = parse_tokens(&mut iter).unwrap();
-  
-        let mut env = Arc::new(Env::new());
-        let res = eval(&tree, &mut env).unwrap();
+#[test]
+fn test_step_limit() {
+    use crate::error::ErrorKind;
+    use crate::limits::Limits;
 
-        if let Str(hello) = res {
-            assert_eq!(hello, "hello");
-        } else {
-            panic!("Unexpected result.");
+    let interpreter = Interpreter::new();
+    interpreter.set_limits(Limits {
+        max_steps: Some(100),
+        ..Limits::default()
+    });
+    let err = interpreter
+        .eval_str("(defun loop () (loop)) (loop)")
+        .unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        ErrorKind::ResourceLimit {
+            resource: "steps",
+            ..
         }
+    ));
+    // Catching it doesn't buy more steps.
+    let err = interpreter
+        .eval_str("(try (loop) (catch e (loop)))")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Resource limit exceeded: more than 100 steps"
+    );
+}
+
+#[test]
+fn test_allocation_limit() {
+    use crate::limits::Limits;
+
+    let interpreter = Interpreter::new();
+    interpreter.set_limits(Limits {
+        max_allocation: Some(1000),
+        ..Limits::default()
+    });
+    let err = interpreter
+        .eval_str(
+            "(def cells (atom nil))
+             (dotimes 100000 (lambda (i) (swap! cells (lambda (l) (cons i l)))))",
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Resource limit exceeded: more than 1000 cells and string bytes"
+    );
+}
+
+#[test]
+fn test_thread_limit() {
+    use crate::limits::Limits;
+
+    let interpreter = Interpreter::new();
+    interpreter.set_limits(Limits {
+        max_threads: Some(2),
+        ..Limits::default()
+    });
+    let res = interpreter
+        .eval_str(
+            "(def f (lambda () 1))
+             (list (thread/join (thread/spawn f)) (thread/join (thread/spawn f))
+                   (try (thread/spawn f) (catch :resource-limit e :refused)))",
+        )
+        .unwrap();
+    assert_eq!(format!("{res}"), "(1 1 :refused )");
+}
+
+#[test]
+fn test_sandbox() {
+    use crate::interpreter::PURE_BUILTINS;
+
+    let sandbox = Interpreter::new().sandbox(PURE_BUILTINS);
+    let res = sandbox
+        .eval_str("(defun square (x) (* x x)) (list (square 7) (if nil 1 2) (get {:a 1} :a))")
+        .unwrap();
+    assert_eq!(format!("{res}"), "(49 2 1 )");
+}
+
+#[test]
+fn test_sandbox_denies_the_rest() {
+    use crate::error::ErrorKind;
+    use crate::interpreter::PURE_BUILTINS;
+
+    let interpreter = Interpreter::new();
+    interpreter.eval_str("(defun secret () 42)").unwrap();
+    let sandbox = interpreter.sandbox(PURE_BUILTINS);
+    for src in ["(thread/spawn (lambda () 1))", "(print 1)", "(secret)"] {
+        let err = sandbox.eval_str(src).unwrap_err();
+        assert!(
+            matches!(err.kind(), ErrorKind::Permission(_)),
+            "{src}: {err}"
+        );
+    }
+    // Code can still define the names for itself.
+    let res = sandbox.eval_str("(defun print (x) x) (print 3)").unwrap();
+    assert!(matches!(res, Num(3)));
+}
+
+#[test]
+fn test_nested_sandboxes() {
+    // A sandbox inside a sandbox can't allow more than it has.
+    let res = eval_str(
+        "(list (sandbox (:pure) (+ 1 2))
+               (try (sandbox (+) (car (list 1))) (catch :permission-error e (:message e)))
+               (sandbox (sandbox try) (sandbox (print try)
+                 (try (print 1) (catch :permission-error e :denied)))))",
+    )
+    .unwrap();
+    assert_eq!(format!("{res}"), "(3 Permission denied: car :denied )");
+}
+
+#[test]
+fn test_sandbox_returns_values() {
+    let res = eval_str("(sandbox (gensym) (gensym))");
+    assert!(matches!(res, Ok(Symbol(_))), "{res:?}");
+}
+
+#[test]
+fn test_sandbox_gets_builtin_values() {
+    let interpreter = Interpreter::new();
+    let res = interpreter
+        .eval_str("(defun car (x) :mine) (sandbox (car list) (car (list 1 2)))")
+        .unwrap();
+    assert!(matches!(res, Num(1)));
+
+    // Redefining a denied name doesn't bring the builtin back, not even in a
+    // fork.
+    let sandbox = interpreter.sandbox(&["defun", "sandbox", "list"]);
+    sandbox.eval_str("(defun car (x) :mine)").unwrap();
+    for sandbox in [sandbox.clone(), sandbox.fork()] {
+        let res = sandbox.eval_str("(sandbox (car list) (car (list 1 2)))");
+        assert_eq!(res.unwrap_err().to_string(), "Permission denied: car");
     }
+}
+
+#[test]
+fn test_sandbox_keeps_allowed_host_functions() {
+    use crate::error::ErrorKind;
+    use std::sync::Arc;
+
+    let interpreter = Interpreter::new();
+    interpreter.register_fn("add", |a: i64, b: i64| a + b);
+    interpreter.register_fn("secret", || 42);
+    interpreter.register_fn("open-document", |title: String| {
+        Object::new(Document {
+            words: vec![],
+            title,
+        })
+    });
+    interpreter.register_method::<Document, _>("title", |doc: Arc<Document>| doc.title.clone());
+    interpreter
+        .register_method::<Document, _>("words", |doc: Arc<Document>| doc.words.len() as i64);
+
+    let sandbox = interpreter.sandbox(&["add", "open-document", "invoke", "title", "list"]);
+    let res = sandbox
+        .eval_str("(list (add 1 2) (invoke (open-document \"notes\") :title))")
+        .unwrap();
+    assert_eq!(format!("{res}"), "(3 notes )");
+    let err = sandbox.eval_str("(secret)").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Permission(_)), "{err}");
+    let err = sandbox
+        .eval_str("(invoke (open-document \"notes\") :words)")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Document has no method words");
+}
+
+#[test]
+fn test_sandbox_keeps_limits() {
+    use crate::limits::Limits;
 
+    let interpreter = Interpreter::new();
+    interpreter.set_limits(Limits {
+        max_steps: Some(100),
+        ..Limits::default()
+    });
+    let sandbox = interpreter.sandbox(&["defun"]);
+    let err = sandbox
+        .eval_str("(defun loop () (loop)) (loop)")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Resource limit exceeded: more than 100 steps"
+    );
 }
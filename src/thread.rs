@@ -0,0 +1,77 @@
+use crate::cancel::CancelToken;
+use crate::env::Env;
+use crate::error::LispError;
+use crate::eval::apply;
+use crate::exp::*;
+use crate::future::Promise;
+use crate::limits;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// A thread started from Lisp delivers the result of its function to a
+// promise that joining waits for. Joining again gives the same result,
+// errors are raised again in every thread that joins.
+
+struct Inner {
+    result: Arc<Promise>,
+    joined: AtomicBool,
+    token: CancelToken,
+}
+
+/// Handle to a thread running a Lisp function. Clones refer to the same
+/// thread.
+#[derive(Clone)]
+pub struct Thread(Arc<Inner>);
+
+impl Thread {
+    /// Calls function without arguments on a new thread. The interpreter of
+    /// env keeps track of it until it's joined. Cancelling the code that
+    /// spawned it cancels the thread as well, and the thread counts against
+    /// the same limits.
+    pub(crate) fn spawn(function: Exp, env: &Arc<Env>) -> Result<Thread, LispErr> {
+        limits::start_thread()?;
+        let thread_env = env.clone();
+        let token = CancelToken::spawned();
+        let thread_token = token.clone();
+        let meter = limits::current();
+        let result = Promise::new();
+        let delivered = result.clone();
+        std::thread::spawn(move || {
+            let run = || thread_token.run(|| apply(&function, vec![], &thread_env));
+            let res = catch_unwind(AssertUnwindSafe(|| limits::metered(meter, run)))
+                .unwrap_or_else(|payload| Err(LispError::panic(&"thread", payload)));
+            delivered.deliver(res);
+        });
+        let thread = Thread(Arc::new(Inner {
+            result,
+            joined: AtomicBool::new(false),
+            token,
+        }));
+        env.interpreter().add_thread(thread.clone());
+        Ok(thread)
+    }
+
+    /// Waits for the thread to finish and gives back what its function
+    /// returned or raised, or a cancelled error if the code waiting is
+    /// cancelled first.
+    pub fn join(&self) -> Result<Exp, LispErr> {
+        let res = self.0.result.wait(None).unwrap();
+        if self.0.result.is_realized() {
+            self.0.joined.store(true, Ordering::Release);
+        }
+        res
+    }
+
+    /// Makes the thread stop at the next call of a Lisp function, with a
+    /// cancelled error for whoever joins it.
+    pub fn cancel(&self) {
+        self.0.token.cancel();
+    }
+
+    /// Whether someone has waited for the thread already.
+    pub fn is_joined(&self) -> bool {
+        self.0.joined.load(Ordering::Acquire)
+    }
+}
@@ -1,9 +1,148 @@
-pub fn tokenize(expr: String) -> Vec<String> {
-    let replaced = expr
-        .replace("\n", "")
-        .replace("\"", " \" ")
-        .replace(")", " ) ")
-        .replace("(", " ( ")
-        .replace(")", " ) ");
-    replaced.split_whitespace().map(|x| x.to_string()).collect()
+use crate::error::Span;
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Debug, PartialEq)]
+pub enum TokenKind {
+    /// Delimiters and atoms, with their source text.
+    Atom,
+    /// A string literal, with its unescaped content.
+    Str,
+    /// A string literal missing its closing quote.
+    Unterminated,
+}
+
+#[derive(Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Span,
+}
+
+// Only atoms compare equal to text, so the string "(" is never a paren.
+impl PartialEq<str> for Token {
+    fn eq(&self, other: &str) -> bool {
+        self.kind == TokenKind::Atom && self.text == other
+    }
+}
+
+// Characters of the source along with the position of the next one.
+struct Source<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: u32,
+    column: u32,
+}
+
+impl Source<'_> {
+    fn span(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+}
+
+impl Iterator for Source<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+}
+
+pub fn tokenize(expr: String) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut start = Span { line: 1, column: 1 };
+    let mut chars = Source {
+        chars: expr.chars().peekable(),
+        line: 1,
+        column: 1,
+    };
+
+    fn flush(current: &mut String, start: Span, tokens: &mut Vec<Token>) {
+        if !current.is_empty() {
+            tokens.push(Token {
+                kind: TokenKind::Atom,
+                text: std::mem::take(current),
+                span: start,
+            });
+        }
+    }
+
+    loop {
+        let span = chars.span();
+        let Some(c) = chars.next() else {
+            break;
+        };
+        if current.is_empty() {
+            start = span;
+        }
+        let token = |text: &str| Token {
+            kind: TokenKind::Atom,
+            text: text.into(),
+            span,
+        };
+        match c {
+            '(' | ')' | '{' | '}' => {
+                flush(&mut current, start, &mut tokens);
+                tokens.push(token(&c.to_string()));
+            }
+            '"' => {
+                flush(&mut current, start, &mut tokens);
+                let mut string = String::new();
+                let mut kind = TokenKind::Unterminated;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => {
+                            kind = TokenKind::Str;
+                            break;
+                        }
+                        '\\' => match chars.next() {
+                            Some('n') => string.push('\n'),
+                            Some('t') => string.push('\t'),
+                            Some(other) => string.push(other),
+                            None => break,
+                        },
+                        other => string.push(other),
+                    }
+                }
+                tokens.push(Token {
+                    kind,
+                    text: string,
+                    span,
+                });
+            }
+            // Character literals: the first character after #\ is always part
+            // of the token, so #\( and #\space both work.
+            '#' if current.is_empty() && chars.peek() == Some(&'\\') => {
+                current.push(c);
+                current.push(chars.next().unwrap());
+                if let Some(c) = chars.next() {
+                    current.push(c);
+                }
+            }
+            '#' if current.is_empty() && chars.peek() == Some(&'{') => {
+                tokens.push(token("#{"));
+                chars.next();
+            }
+            '@' if current.is_empty() => tokens.push(token("@")),
+            c if c.is_whitespace() => flush(&mut current, start, &mut tokens),
+            c => current.push(c),
+        }
+    }
+    flush(&mut current, start, &mut tokens);
+    tokens
 }